@@ -0,0 +1,94 @@
+use std::ops::Range;
+
+use crate::{ray::Ray, vec3::{Point3, Vec3}};
+
+/// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vec3::from(f32::INFINITY),
+            max: Vec3::from(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Point3>) -> Self {
+        points.into_iter().fold(Aabb::empty(), Aabb::grow)
+    }
+
+    pub fn grow(self, p: Point3) -> Self {
+        Aabb {
+            min: self.min.zip_with(p, f32::min),
+            max: self.max.zip_with(p, f32::max),
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Self {
+        Aabb {
+            min: self.min.zip_with(other.min, f32::min),
+            max: self.max.zip_with(other.max, f32::max),
+        }
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let e = self.extent();
+        if e.x() < 0.0 {
+            return 0.0;
+        }
+        2.0 * (e.x() * e.y() + e.y() * e.z() + e.z() * e.x())
+    }
+
+    /// Index of the longest axis.
+    pub fn major_axis(&self) -> usize {
+        let e = self.extent();
+        if e.x() >= e.y() && e.x() >= e.z() {
+            0
+        } else if e.y() >= e.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Slab test; returns the parametric range of the ray inside the box.
+    pub fn hit(&self, ray: &Ray, t_range: Range<f32>) -> Option<Range<f32>> {
+        let mut t_min = t_range.start;
+        let mut t_max = t_range.end;
+        for axis in 0..3 {
+            let inv_d = 1.0 / component(ray.direction, axis);
+            let mut t0 = (component(self.min, axis) - component(ray.origin, axis)) * inv_d;
+            let mut t1 = (component(self.max, axis) - component(ray.origin, axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some(t_min..t_max)
+    }
+}
+
+#[inline]
+pub fn component(v: Vec3, axis: usize) -> f32 {
+    match axis {
+        0 => v.x(),
+        1 => v.y(),
+        _ => v.z(),
+    }
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, f32::consts::PI, sync::Arc};
+
+use crate::{aabb::Aabb, environment::luminance, hittable::HitRecord, light_tree::LightBounds, material::Material, mesh::TriangleMesh, onb::Onb, ray::Ray, sphere::{Sphere, sphere_uv}, vec3::{Color, Point3, Vec3}};
+
+/// Geometry whose material gives off light.
+#[derive(Debug, Clone)]
+pub enum Emitter {
+    Mesh(Arc<TriangleMesh>),
+    Sphere(Arc<Sphere>),
+}
+
+impl From<TriangleMesh> for Emitter {
+    fn from(mesh: TriangleMesh) -> Self {
+        Emitter::Mesh(Arc::new(mesh))
+    }
+}
+
+impl From<Sphere> for Emitter {
+    fn from(sphere: Sphere) -> Self {
+        Emitter::Sphere(Arc::new(sphere))
+    }
+}
+
+impl Emitter {
+    pub fn material(&self) -> &Material {
+        match self {
+            Emitter::Mesh(mesh) => mesh.material(),
+            Emitter::Sphere(sphere) => sphere.material(),
+        }
+    }
+}
+
+/// The primitives of every emissive object in a scene, each sampled by
+/// solid angle once a light has been chosen.
+#[derive(Debug, Clone, Default)]
+pub struct AreaLights {
+    emitters: Vec<Emitter>,
+    /// Index of each emitter's first primitive.
+    offsets: Vec<usize>,
+    /// The scene object behind each emitter.
+    objects: Vec<usize>,
+    /// Emitter of each scene object, to recognise hits on lights.
+    by_object: HashMap<usize, usize>,
+    bounds: Vec<LightBounds>,
+}
+
+/// A point on an emitter as seen from a shading point.
+#[derive(Debug, Clone, Copy)]
+pub struct AreaLightSample {
+    pub wi: Vec3,
+    pub radiance: Color,
+    pub distance: f32,
+    /// Solid-angle density at the shading point, given the primitive.
+    pub pdf: f32,
+}
+
+impl AreaLights {
+    pub fn is_empty(&self) -> bool {
+        self.emitters.is_empty()
+    }
+
+    pub fn emitters(&self) -> &[Emitter] {
+        &self.emitters
+    }
+
+    /// Number of primitives over all emitters.
+    pub fn len(&self) -> usize {
+        self.bounds.len()
+    }
+
+    /// Adds `emitter`, which hits report as scene object `object`.
+    pub fn add(&mut self, emitter: Emitter, object: usize) {
+        let index = self.emitters.len();
+        self.offsets.push(self.bounds.len());
+        self.objects.push(object);
+        self.by_object.insert(object, index);
+        match &emitter {
+            Emitter::Mesh(mesh) => self.bounds.extend((0..mesh.triangle_count()).map(|i| triangle_bounds(mesh, i))),
+            Emitter::Sphere(sphere) => self.bounds.push(sphere_bounds(sphere)),
+        }
+        self.emitters.push(emitter);
+    }
+
+    /// Position and power of every primitive, for choosing between them.
+    pub fn light_bounds(&self) -> &[LightBounds] {
+        &self.bounds
+    }
+
+    /// Which emitter primitive `index` belongs to.
+    pub fn emitter_of(&self, index: usize) -> usize {
+        self.offsets.partition_point(|&o| o <= index) - 1
+    }
+
+    /// The scene object emitter `emitter` was added as.
+    pub fn object_of(&self, emitter: usize) -> usize {
+        self.objects[emitter]
+    }
+
+    /// The emitter `rec` lies on, if it is one.
+    pub fn emitter_at(&self, rec: &HitRecord) -> Option<usize> {
+        self.by_object.get(&rec.object).copied()
+    }
+
+    /// Picks a point on primitive `index` as seen from `p`.
+    pub fn sample(&self, index: usize, p: Point3, u1: f32, u2: f32) -> Option<AreaLightSample> {
+        let emitter_index = self.emitter_of(index);
+        match &self.emitters[emitter_index] {
+            Emitter::Mesh(mesh) => sample_triangle(mesh, index - self.offsets[emitter_index], p, u1, u2),
+            Emitter::Sphere(sphere) => sample_sphere(sphere, p, u1, u2),
+        }
+    }
+
+    /// The primitive `ray` found at `rec` and the density with which
+    /// `sample` would have picked that point, or `None` if it isn't a light.
+    pub fn pdf(&self, ray: &Ray, rec: &HitRecord) -> Option<(usize, f32)> {
+        let emitter_index = self.emitter_at(rec)?;
+        let index = self.offsets[emitter_index] + rec.primitive;
+        let pdf = match &self.emitters[emitter_index] {
+            Emitter::Mesh(mesh) => {
+                let ([p0, p1, p2], _) = mesh.triangle(rec.primitive);
+                let cross = (p1 - p0).cross(p2 - p0);
+                let area = 0.5 * cross.length();
+                let distance_squared = (rec.p - ray.origin).length_squared();
+                let cos_light = Vec3::unit_vector(ray.direction).dot(cross.unit_vector()).abs();
+                if area <= 0.0 || cos_light <= 0.0 { 0.0 } else { distance_squared / (area * cos_light) }
+            }
+            Emitter::Sphere(sphere) => cone_extent(sphere, ray.origin).map_or(0.0, |e| 1.0 / (2.0 * PI * e)),
+        };
+        Some((index, pdf))
+    }
+}
+
+fn sample_triangle(mesh: &TriangleMesh, index: usize, p: Point3, u1: f32, u2: f32) -> Option<AreaLightSample> {
+    let ([p0, p1, p2], [uv0, uv1, uv2]) = mesh.triangle(index);
+
+    let r = u1.sqrt();
+    let (b0, b1) = (1.0 - r, u2 * r);
+    let b2 = 1.0 - b0 - b1;
+    let point = b0 * p0 + b1 * p1 + b2 * p2;
+    let u = b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0;
+    let v = b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1;
+
+    let cross = (p1 - p0).cross(p2 - p0);
+    let area = 0.5 * cross.length();
+    let normal = cross.unit_vector();
+
+    let d = point - p;
+    let distance = d.length();
+    if distance <= 0.0 || area <= 0.0 {
+        return None;
+    }
+    let wi = d / distance;
+    let cos_light = wi.dot(normal);
+    if cos_light == 0.0 {
+        return None;
+    }
+
+    let radiance = mesh.material().emission(u, v, point, cos_light < 0.0);
+    let pdf = distance * distance / (area * cos_light.abs());
+    Some(AreaLightSample { wi, radiance, distance, pdf })
+}
+
+/// Uniform over the cone of directions the sphere fills, seen from outside.
+fn sample_sphere(sphere: &Sphere, p: Point3, u1: f32, u2: f32) -> Option<AreaLightSample> {
+    let one_minus_cos_max = cone_extent(sphere, p)?;
+    let to_center = sphere.center() - p;
+    let d2 = to_center.length_squared();
+
+    let cos_theta = 1.0 - u1 * one_minus_cos_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    let wi = Onb::from_w(to_center).local(Vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+
+    // Nearest intersection, or the tangent point when rounding misses.
+    let along = d2.sqrt() * cos_theta;
+    let half_chord = (sphere.radius() * sphere.radius() - (d2 - along * along)).max(0.0).sqrt();
+    let distance = along - half_chord;
+    let point = p + distance * wi;
+
+    let (u, v) = sphere_uv((point - sphere.center()) / sphere.radius());
+    let radiance = sphere.material().emission(u, v, point, true);
+    let pdf = 1.0 / (2.0 * PI * one_minus_cos_max);
+    Some(AreaLightSample { wi, radiance, distance, pdf })
+}
+
+/// One minus the cosine of the half-angle the sphere subtends at `p`,
+/// written to avoid cancellation for distant spheres. `None` from inside.
+fn cone_extent(sphere: &Sphere, p: Point3) -> Option<f32> {
+    let d2 = (sphere.center() - p).length_squared();
+    let r2 = sphere.radius() * sphere.radius();
+    if d2 <= r2 {
+        return None;
+    }
+    let sin2_max = r2 / d2;
+    Some(sin2_max / (1.0 + (1.0 - sin2_max).sqrt()))
+}
+
+/// Area times average radiance, from the corners and the centre, summed over
+/// both sides.
+fn triangle_bounds(mesh: &TriangleMesh, index: usize) -> LightBounds {
+    let (p, uv) = mesh.triangle(index);
+    let area = 0.5 * (p[1] - p[0]).cross(p[2] - p[0]).length();
+    let centre = (p[0] + p[1] + p[2]) / 3.0;
+    let uv_centre = ((uv[0].0 + uv[1].0 + uv[2].0) / 3.0, (uv[0].1 + uv[1].1 + uv[2].1) / 3.0);
+
+    let material = mesh.material();
+    let mut radiance = Vec3::default();
+    for front in [true, false] {
+        radiance = radiance + material.emission(uv_centre.0, uv_centre.1, centre, front);
+        for i in 0..3 {
+            radiance = radiance + material.emission(uv[i].0, uv[i].1, p[i], front);
+        }
+    }
+    LightBounds {
+        bounds: Aabb::from_points(p),
+        power: PI * area * luminance(radiance) / 4.0,
+    }
+}
+
+/// Surface area times radiance averaged over the six points facing the axes.
+fn sphere_bounds(sphere: &Sphere) -> LightBounds {
+    let (c, r) = (sphere.center(), sphere.radius());
+    let axes = [Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0), Vec3(0.0, 0.0, 1.0)];
+    let mut radiance = Vec3::default();
+    for n in axes.iter().flat_map(|&a| [a, -a]) {
+        let (u, v) = sphere_uv(n);
+        radiance = radiance + sphere.material().emission(u, v, c + r * n, true);
+    }
+    LightBounds {
+        bounds: Aabb { min: c - Vec3::from(r), max: c + Vec3::from(r) },
+        power: PI * 4.0 * PI * r * r * luminance(radiance) / 6.0,
+    }
+}
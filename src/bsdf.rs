@@ -0,0 +1,31 @@
+use rand::RngCore;
+
+use crate::{hittable::HitRecord, ray::Ray, vec3::{Color, Vec3}};
+
+/// Extension point for materials defined outside this crate; wrap one in
+/// `Material::Custom` to put it in a scene. Built-in materials implement it
+/// too, through `Material`'s enum fast path.
+///
+/// Directions handed to `eval` and `pdf` are unit vectors in world space
+/// pointing away from the surface.
+pub trait Bsdf: std::fmt::Debug + Send + Sync {
+    /// Samples an outgoing ray, returning it with its weight: the BSDF times
+    /// the cosine over the sampling density. `None` absorbs the path.
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<(Color, Ray)>;
+
+    /// The BSDF times the cosine for light leaving along `wi`, or `None` if
+    /// it can't be evaluated for arbitrary directions (specular and
+    /// stochastically layered materials). Light sampling needs this.
+    fn eval(&self, _ray_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Option<Color> {
+        None
+    }
+
+    /// Solid-angle density with which `scatter` picks `wi`, when known.
+    fn pdf(&self, _ray_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Option<f32> {
+        None
+    }
+
+    fn emitted(&self, _ray_in: &Ray, _rec: &HitRecord) -> Color {
+        Vec3::default()
+    }
+}
@@ -0,0 +1,104 @@
+use std::ops::Range;
+
+use crate::{aabb::{Aabb, component}, ray::Ray};
+
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Bounding volume hierarchy over anything that can be boxed. It only orders
+/// primitive indices; the owner keeps the primitives and tests them.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    /// For leaves the first entry in `indices`, for interior nodes the index
+    /// of the second child; the first child always follows its parent.
+    offset: usize,
+    /// Zero for interior nodes.
+    count: usize,
+}
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build_node(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or_else(Aabb::empty, |n| n.bounds)
+    }
+
+    fn build_node(&mut self, bounds: &[Aabb], start: usize, end: usize) -> usize {
+        let node_bounds = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |b, &i| b.union(bounds[i]));
+        let index = self.nodes.len();
+        self.nodes.push(Node { bounds: node_bounds, offset: start, count: end - start });
+
+        if end - start <= MAX_LEAF_SIZE {
+            return index;
+        }
+
+        // Median split along the axis where the centroids spread the most.
+        let centroids = Aabb::from_points(self.indices[start..end].iter().map(|&i| bounds[i].centroid()));
+        let axis = centroids.major_axis();
+        let mid = (start + end) / 2;
+        self.indices[start..end].select_nth_unstable_by(mid - start, |&a, &b| {
+            component(bounds[a].centroid(), axis)
+                .partial_cmp(&component(bounds[b].centroid(), axis))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        self.build_node(bounds, start, mid);
+        let second = self.build_node(bounds, mid, end);
+        self.nodes[index].offset = second;
+        self.nodes[index].count = 0;
+        index
+    }
+
+    /// Finds the closest primitive hit. `hit` tests one primitive within the
+    /// given range and returns the distance along with whatever it produced.
+    pub fn intersect<T>(
+        &self,
+        ray: &Ray,
+        t_range: Range<f32>,
+        mut hit: impl FnMut(usize, Range<f32>) -> Option<(f32, T)>,
+    ) -> Option<T> {
+        let mut nearest = t_range.end;
+        let mut result = None;
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(n) = stack.pop() {
+            let node = self.nodes[n];
+            if node.bounds.hit(ray, t_range.start..nearest).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.offset);
+                stack.push(n + 1);
+                continue;
+            }
+            for &i in &self.indices[node.offset..node.offset + node.count] {
+                if let Some((t, value)) = hit(i, t_range.start..nearest) {
+                    nearest = t;
+                    result = Some(value);
+                }
+            }
+        }
+
+        result
+    }
+}
@@ -1,7 +1,95 @@
-use rand::Rng;
+use std::{f32::consts::PI, io, path::Path};
 
-use crate::{ray::Ray, utility_funcs::*, vec3::*};
+use rand::{Rng, RngCore};
 
+use crate::{environment::luminance, ray::Ray, sampling::Distribution2D, texture::ImageTexture, utility_funcs::*, vec3::*};
+
+/// Turns image coordinates into primary rays. `s` runs left to right and
+/// `t` bottom to top, both over `[0, 1]`. Pixels a projection doesn't
+/// cover, such as those outside a fisheye's image circle, get no ray and
+/// stay black.
+pub trait Projection: Send + Sync {
+    fn ray(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> Option<Ray>;
+}
+
+/// Shape of the lens opening, which out-of-focus highlights take on.
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Disc,
+    /// Straight-bladed iris, made with `Aperture::polygon`.
+    Polygon(Iris),
+    /// Transmission read from a grayscale image spanning the lens diameter.
+    Mask(Distribution2D),
+}
+
+/// The blade count and turn of an `Aperture::Polygon`.
+#[derive(Debug, Clone, Copy)]
+pub struct Iris {
+    blades: u32,
+    rotation: f32,
+}
+
+impl Aperture {
+    /// Iris with `blades` sides, at least three, turned `rotation` degrees
+    /// anticlockwise from a vertex pointing right.
+    pub fn polygon(blades: u32, rotation: f32) -> Result<Self, String> {
+        if blades < 3 {
+            return Err(format!("an iris needs at least 3 blades, not {}", blades));
+        }
+        Ok(Aperture::Polygon(Iris { blades, rotation }))
+    }
+
+    /// Opening shaped like `image`, brighter texels letting more light through.
+    pub fn mask(image: &ImageTexture) -> Self {
+        let weights: Vec<f32> = (0..image.height())
+            .flat_map(|y| (0..image.width()).map(move |x| luminance(image.texel(x, y))))
+            .collect();
+        Aperture::Mask(Distribution2D::new(&weights, image.width()))
+    }
+
+    /// Loads a mask from a PPM, as `ImageTexture::load_ppm` does.
+    pub fn load_mask(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Aperture::mask(&ImageTexture::load_ppm(path)?))
+    }
+
+    /// Distance from the centre to the furthest point of the opening.
+    fn radius(&self) -> f32 {
+        match self {
+            Aperture::Mask(_) => 2f32.sqrt(),
+            _ => 1.0,
+        }
+    }
+
+    /// A point within the opening, which fits the square `[-1, 1]²`.
+    fn sample(&self, u1: f32, u2: f32) -> (f32, f32) {
+        match *self {
+            Aperture::Polygon(Iris { blades, rotation }) => {
+                // Equal triangles fan out from the centre; pick one, then a
+                // point in it.
+                let n = blades as f32;
+                let k = (u1 * n).floor().min(n - 1.0);
+                let u1 = u1 * n - k;
+                let start = degrees_to_radians(rotation) + 2.0 * PI * k / n;
+                let (a, b) = (start, start + 2.0 * PI / n);
+                let r = u1.sqrt();
+                let (wa, wb) = (r * (1.0 - u2), r * u2);
+                (wa * a.cos() + wb * b.cos(), wa * a.sin() + wb * b.sin())
+            }
+            Aperture::Mask(ref distribution) => {
+                // Images are stored top to bottom.
+                let ((x, y), _) = distribution.sample(u1, u2);
+                (2.0 * x - 1.0, 1.0 - 2.0 * y)
+            }
+            Aperture::Disc => {
+                let r = u1.sqrt();
+                let phi = 2.0 * PI * u2;
+                (r * phi.cos(), r * phi.sin())
+            }
+        }
+    }
+}
+
+/// Thin-lens perspective camera.
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
@@ -9,7 +97,10 @@ pub struct Camera {
     vertical: Vec3,
     u:Vec3,
     v:Vec3,
-    lens_radius:f32
+    lens_radius:f32,
+    aspect_ratio: f32,
+    aperture: Aperture,
+    cat_eye: f32,
 }
 
 impl Camera {
@@ -17,43 +108,495 @@ impl Camera {
         lookfrom:Point3,
         lookat:Point3,
         vup:Vec3,
-        vfov:f32, 
+        vfov:f32,
         aspect_ratio:f32,
         aperture:f32,
         focus_dist:f32
     ) -> Self {
         let theta = degrees_to_radians(vfov);
-        let h = f32::tan(theta/2.0);        
+        let h = f32::tan(theta/2.0);
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = Vec3::unit_vector(lookfrom - lookat);
-        let u = Vec3::unit_vector(vup.cross(w));
-        let v = w.cross(u);
-        
+        let (u, v, w) = look_frame(lookfrom, lookat, vup);
+
         let origin = lookfrom;
         let horizontal = focus_dist * viewport_width * u;
         let vertical = focus_dist * viewport_height * v;
         let lower_left_corner = origin - horizontal/2.0 - vertical/2.0 - focus_dist*w;
         let lens_radius = aperture / 2.0;
 
-        Self { 
+        Self {
             origin,
             lower_left_corner,
             horizontal,
             vertical,
             u, v,
-            lens_radius
+            lens_radius,
+            aspect_ratio,
+            aperture: Aperture::Disc,
+            cat_eye: 0.0,
+        }
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    /// Clips the opening with the lens barrel towards the edges of the
+    /// frame, so bokeh there narrows into cat's eyes. The barrel is a circle
+    /// just around the opening, shifted by up to `strength` times its radius
+    /// at the corners; at 1 a round opening keeps about 39% of its area
+    /// there, and 0 turns it off. Only the shape changes, the corners aren't
+    /// darkened.
+    pub fn with_cat_eye(mut self, strength: f32) -> Self {
+        self.cat_eye = strength.clamp(0.0, 1.0);
+        self
+    }
+
+    /// A point on the lens, in units of its radius, for the pixel at `s`, `t`.
+    fn lens_point(&self, s: f32, t: f32, rng: &mut dyn RngCore) -> (f32, f32) {
+        // Offset from the centre of the frame, 1 at the corners, in units
+        // of the barrel's radius.
+        let norm = (self.aspect_ratio * self.aspect_ratio + 1.0).sqrt();
+        let radius = self.aperture.radius();
+        let barrel = (
+            radius * self.cat_eye * (2.0 * s - 1.0) * self.aspect_ratio / norm,
+            radius * self.cat_eye * (2.0 * t - 1.0) / norm,
+        );
+
+        // Rejecting points the barrel hides keeps the highlights evenly
+        // lit; a mask that leaves nothing visible gives up after a while and
+        // falls back on the centre, which the barrel never hides.
+        for _ in 0..64 {
+            let point = self.aperture.sample(rng.gen(), rng.gen());
+            let (dx, dy) = (point.0 - barrel.0, point.1 - barrel.1);
+            if self.cat_eye == 0.0 || dx * dx + dy * dy <= radius * radius {
+                return point;
+            }
         }
+        (0.0, 0.0)
     }
 
     pub fn get_ray(&self, s:f32, t:f32, rng: &mut impl Rng) -> Ray {
-        let rd:Vec3 = self.lens_radius * Vec3::random_in_unit_disc(rng);
-        let offset = rd.x() * self.u + rd.y() * self.v;
+        let (x, y) = self.lens_point(s, t, rng);
+        let offset = self.lens_radius * (x * self.u + y * self.v);
 
         Ray {
-            origin: self.origin,
+            origin: self.origin + offset,
             direction: self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset,
         }
     }
-}
\ No newline at end of file
+}
+
+impl Projection for Camera {
+    fn ray(&self, s: f32, t: f32, mut rng: &mut dyn RngCore) -> Option<Ray> {
+        Some(self.get_ray(s, t, &mut rng))
+    }
+}
+
+/// Parallel projection for technical drawings: every ray shares the view
+/// direction and `view_height` world units fill the image vertically.
+pub struct Orthographic {
+    lower_left_corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl Orthographic {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, view_height: f32, aspect_ratio: f32) -> Self {
+        let (u, v, w) = look_frame(lookfrom, lookat, vup);
+        let horizontal = (aspect_ratio * view_height) * u;
+        let vertical = view_height * v;
+        Orthographic {
+            lower_left_corner: lookfrom - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            direction: -w,
+        }
+    }
+}
+
+impl Projection for Orthographic {
+    fn ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        Some(Ray::new(self.lower_left_corner + s * self.horizontal + t * self.vertical, self.direction))
+    }
+}
+
+/// Full 360° by 180° latitude-longitude panorama, for VR previews. The view
+/// direction lands in the middle of the image, which has to be twice as
+/// wide as it is tall: the angles ignore the aspect ratio, so any other
+/// shape stretches the panorama.
+pub struct Equirectangular {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Equirectangular {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3) -> Self {
+        let (u, v, w) = look_frame(lookfrom, lookat, vup);
+        Equirectangular { origin: lookfrom, u, v, w }
+    }
+}
+
+impl Projection for Equirectangular {
+    fn ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let phi = (s - 0.5) * 2.0 * PI;
+        let elevation = (t - 0.5) * PI;
+        let direction = elevation.cos() * (phi.sin() * self.u - phi.cos() * self.w) + elevation.sin() * self.v;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// How a fisheye lens maps the angle off its axis to distance from the
+/// image centre.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle; the usual choice for domes.
+    Equidistant,
+    /// Equal solid angles cover equal image areas, like most real fisheyes.
+    Equisolid,
+}
+
+/// Fisheye lens whose `fov` spans the width of the image and may exceed 180°.
+/// Outside the circle that width fills, there is no picture.
+pub struct Fisheye {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    half_fov: f32,
+    aspect_ratio: f32,
+    mapping: FisheyeMapping,
+}
+
+impl Fisheye {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, fov: f32, aspect_ratio: f32, mapping: FisheyeMapping) -> Self {
+        let (u, v, w) = look_frame(lookfrom, lookat, vup);
+        Fisheye {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            half_fov: degrees_to_radians(fov.clamp(0.0, 360.0)) / 2.0,
+            aspect_ratio,
+            mapping,
+        }
+    }
+}
+
+impl Projection for Fisheye {
+    fn ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        // Distances in half-widths of the image.
+        let x = 2.0 * s - 1.0;
+        let y = (2.0 * t - 1.0) / self.aspect_ratio;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.half_fov / 2.0).sin()).min(1.0).asin(),
+        }
+        .min(PI);
+        let (cos_phi, sin_phi) = if r > 0.0 { (x / r, y / r) } else { (1.0, 0.0) };
+
+        let direction = theta.sin() * (cos_phi * self.u + sin_phi * self.v) - theta.cos() * self.w;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// Cylindrical panorama: `fov` degrees around the vertical axis across the
+/// width, with straight verticals and the height scaled to keep pixels
+/// square on the cylinder.
+pub struct Cylindrical {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    fov: f32,
+    aspect_ratio: f32,
+}
+
+impl Cylindrical {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, fov: f32, aspect_ratio: f32) -> Self {
+        let (u, v, w) = look_frame(lookfrom, lookat, vup);
+        Cylindrical { origin: lookfrom, u, v, w, fov: degrees_to_radians(fov), aspect_ratio }
+    }
+}
+
+impl Projection for Cylindrical {
+    fn ray(&self, s: f32, t: f32, _rng: &mut dyn RngCore) -> Option<Ray> {
+        let phi = (s - 0.5) * self.fov;
+        let height = (t - 0.5) * self.fov / self.aspect_ratio;
+        let direction = phi.sin() * self.u + height * self.v - phi.cos() * self.w;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// Builds a camera from a scene description, one setting per line:
+///
+/// ```text
+/// projection fisheye equisolid   # or perspective, orthographic,
+///                                # equirectangular, cylindrical
+/// lookfrom 13 2 3
+/// lookat 0 0 0
+/// vup 0 1 0
+/// fov 180                        # degrees: vertical for perspective,
+///                                # horizontal otherwise
+/// height 4                       # orthographic view height
+/// aperture 0.1                   # lens diameter, perspective only
+/// focus_distance 10
+/// blades 6                       # polygonal iris of 3 or more, rotated by
+/// blade_rotation 15              # this many degrees
+/// aperture_mask heart.ppm        # or any grayscale PPM
+/// cat_eye 0.5                    # barrel clipping, 0 to 1
+/// ```
+///
+/// Anything left out takes a default; the focus distance defaults to the
+/// distance to `lookat`. An equirectangular projection needs an
+/// `aspect_ratio` of 2.
+pub fn parse(source: &str, aspect_ratio: f32) -> Result<Box<dyn Projection>, String> {
+    let mut projection = "perspective".to_string();
+    let mut mapping = FisheyeMapping::Equidistant;
+    let mut lookfrom = Vec3(0.0, 0.0, 0.0);
+    let mut lookat = Vec3(0.0, 0.0, -1.0);
+    let mut vup = Vec3(0.0, 1.0, 0.0);
+    let mut fov = 90.0;
+    let mut height = 2.0;
+    let mut aperture = 0.0;
+    let mut focus_distance = None;
+    let mut blades = None;
+    let mut blade_rotation = 0.0;
+    let mut mask = None;
+    let mut cat_eye = 0.0;
+
+    for (line_no, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: String| format!("line {}: {}", line_no + 1, msg);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (key, args) = (words[0], &words[1..]);
+
+        let number = |s: &str| s.parse::<f32>().map_err(|_| err(format!("expected a number, found `{}`", s)));
+        let scalar = || match args {
+            [a] => number(a),
+            _ => Err(err(format!("`{}` takes one number", key))),
+        };
+        let vector = || match args {
+            [x, y, z] => Ok(Vec3(number(x)?, number(y)?, number(z)?)),
+            _ => Err(err(format!("`{}` takes three numbers", key))),
+        };
+
+        match key {
+            "projection" => {
+                match args {
+                    ["fisheye", "equidistant"] | ["fisheye"] => mapping = FisheyeMapping::Equidistant,
+                    ["fisheye", "equisolid"] => mapping = FisheyeMapping::Equisolid,
+                    ["perspective"] | ["orthographic"] | ["equirectangular"] | ["cylindrical"] => {}
+                    _ => return Err(err(format!("unknown projection `{}`", args.join(" ")))),
+                }
+                projection = args[0].to_string();
+            }
+            "lookfrom" => lookfrom = vector()?,
+            "lookat" => lookat = vector()?,
+            "vup" => vup = vector()?,
+            "fov" => fov = scalar()?,
+            "height" => height = scalar()?,
+            "aperture" => aperture = scalar()?,
+            "focus_distance" => focus_distance = Some(scalar()?),
+            "blades" => match args {
+                [n] => match n.parse::<u32>() {
+                    Ok(count) => blades = Some((count, line_no + 1)),
+                    Err(_) => return Err(err(format!("expected a blade count, found `{}`", n))),
+                },
+                _ => return Err(err("`blades` takes one count".to_string())),
+            },
+            "blade_rotation" => blade_rotation = scalar()?,
+            "aperture_mask" => match args {
+                [path] => mask = Some(Aperture::load_mask(path).map_err(|e| err(format!("couldn't load `{}`: {}", path, e)))?),
+                _ => return Err(err("`aperture_mask` takes one path".to_string())),
+            },
+            "cat_eye" => cat_eye = scalar()?,
+            _ => return Err(err(format!("unknown setting `{}`", key))),
+        }
+    }
+
+    Ok(match projection.as_str() {
+        "orthographic" => Box::new(Orthographic::new(lookfrom, lookat, vup, height, aspect_ratio)),
+        "equirectangular" if (aspect_ratio - 2.0).abs() > 1e-3 => {
+            return Err(format!("an equirectangular image must be twice as wide as it is tall, not {}:1", aspect_ratio))
+        }
+        "equirectangular" => Box::new(Equirectangular::new(lookfrom, lookat, vup)),
+        "fisheye" => Box::new(Fisheye::new(lookfrom, lookat, vup, fov, aspect_ratio, mapping)),
+        "cylindrical" => Box::new(Cylindrical::new(lookfrom, lookat, vup, fov, aspect_ratio)),
+        _ => {
+            let focus_distance = focus_distance.unwrap_or_else(|| (lookat - lookfrom).length());
+            let shape = match (mask, blades) {
+                (Some(mask), _) => mask,
+                (None, Some((blades, line))) => Aperture::polygon(blades, blade_rotation).map_err(|e| format!("line {}: {}", line, e))?,
+                (None, None) => Aperture::Disc,
+            };
+            Box::new(
+                Camera::new(lookfrom, lookat, vup, fov, aspect_ratio, aperture, focus_distance)
+                    .with_aperture(shape)
+                    .with_cat_eye(cat_eye),
+            )
+        }
+    })
+}
+
+/// Right, up and backward unit vectors of a camera at `lookfrom` facing
+/// `lookat`.
+fn look_frame(lookfrom: Point3, lookat: Point3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = Vec3::unit_vector(lookfrom - lookat);
+    let u = Vec3::unit_vector(vup.cross(w));
+    let v = w.cross(u);
+    (u, v, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn ray(camera: &dyn Projection, s: f32, t: f32) -> Option<Ray> {
+        camera.ray(s, t, &mut StdRng::seed_from_u64(3))
+    }
+
+    fn error(source: &str, aspect_ratio: f32) -> String {
+        match parse(source, aspect_ratio) {
+            Ok(_) => panic!("`{}` parsed", source),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn every_projection_looks_at_lookat() {
+        for (projection, aspect_ratio) in [
+            ("perspective", 1.5),
+            ("orthographic", 1.5),
+            ("equirectangular", 2.0),
+            ("fisheye equidistant", 1.5),
+            ("fisheye equisolid", 1.5),
+            ("cylindrical", 1.5),
+        ] {
+            let source = format!("projection {}\nlookfrom 1 2 3\nlookat 1 2 0  # straight ahead\n\nfov 120\n", projection);
+            let camera = parse(&source, aspect_ratio).unwrap();
+            let centre = ray(camera.as_ref(), 0.5, 0.5).unwrap();
+            let d = centre.direction.unit_vector();
+            assert!(d.z() < -0.9999, "{}: {:?}", projection, d);
+        }
+    }
+
+    #[test]
+    fn malformed_lines_are_reported() {
+        for (source, message) in [
+            ("fov wide", "line 1: expected a number, found `wide`"),
+            ("# a comment\nlookat 1 2", "line 2: `lookat` takes three numbers"),
+            ("aperture 0.1 0.2", "`aperture` takes one number"),
+            ("projection stereographic", "unknown projection `stereographic`"),
+            ("projection fisheye fancy", "unknown projection `fisheye fancy`"),
+            ("zoom 2", "unknown setting `zoom`"),
+            ("blades -3", "expected a blade count, found `-3`"),
+            ("blades", "`blades` takes one count"),
+            ("blades 2", "an iris needs at least 3 blades, not 2"),
+            ("blades 0", "an iris needs at least 3 blades, not 0"),
+            ("aperture_mask /nonexistent/mask.ppm", "couldn't load `/nonexistent/mask.ppm`"),
+        ] {
+            let e = error(source, 1.5);
+            assert!(e.contains(message), "{}: {}", source, e);
+        }
+    }
+
+    #[test]
+    fn rays_through_a_pixel_meet_on_the_focal_plane() {
+        let camera = Camera::new(Vec3::default(), Vec3(0.0, 0.0, -1.0), Vec3(0.0, 1.0, 0.0), 40.0, 1.5, 2.0, 5.0);
+        let mut rng = StdRng::seed_from_u64(4);
+        // Where a ray crosses the plane z = -5.
+        let focus = |ray: Ray| ray.at((-5.0 - ray.origin.z()) / ray.direction.z());
+        let sharp = focus(camera.get_ray(0.3, 0.6, &mut rng));
+        for _ in 0..50 {
+            assert!((focus(camera.get_ray(0.3, 0.6, &mut rng)) - sharp).length() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn the_barrel_spares_the_middle_of_the_frame() {
+        let open = ImageTexture::new(2, 2, vec![Vec3::from(1.0); 4]);
+        let camera = Camera::new(Vec3::default(), Vec3(0.0, 0.0, -1.0), Vec3(0.0, 1.0, 0.0), 40.0, 1.5, 0.1, 1.0)
+            .with_aperture(Aperture::mask(&open))
+            .with_cat_eye(1.0);
+        let mut rng = StdRng::seed_from_u64(8);
+
+        // The whole square mask gets through in the middle...
+        let points: Vec<_> = (0..2000).map(|_| camera.lens_point(0.5, 0.5, &mut rng)).collect();
+        assert!(points.iter().any(|&(x, y)| x < -0.9 && y < -0.9));
+        assert!(points.iter().any(|&(x, y)| x > 0.9 && y > 0.9));
+
+        // ...but the bottom left corner of the frame loses the top right of
+        // the lens.
+        let points: Vec<_> = (0..2000).map(|_| camera.lens_point(0.0, 0.0, &mut rng)).collect();
+        assert!(points.iter().any(|&(x, y)| x < -0.9 && y < -0.9));
+        assert!(points.iter().all(|&(x, y)| x + y < 0.5));
+    }
+
+    #[test]
+    fn hidden_openings_fall_back_on_the_lens_centre() {
+        // Only the top right quarter is open, which the barrel hides at the
+        // bottom left corner of the frame.
+        let mut texels = vec![Vec3::default(); 4];
+        texels[1] = Vec3::from(1.0);
+        let camera = Camera::new(Vec3::default(), Vec3(0.0, 0.0, -1.0), Vec3(0.0, 1.0, 0.0), 40.0, 1.5, 0.1, 1.0)
+            .with_aperture(Aperture::mask(&ImageTexture::new(2, 2, texels)))
+            .with_cat_eye(1.0);
+        let mut rng = StdRng::seed_from_u64(8);
+        for _ in 0..100 {
+            assert_eq!(camera.lens_point(0.0, 0.0, &mut rng), (0.0, 0.0));
+        }
+        assert!((0..100).all(|_| camera.lens_point(0.5, 0.5, &mut rng) != (0.0, 0.0)));
+    }
+
+    #[test]
+    fn irises_need_three_blades() {
+        assert_eq!(Aperture::polygon(2, 0.0).unwrap_err(), "an iris needs at least 3 blades, not 2");
+        assert!(Aperture::polygon(0, 0.0).is_err());
+
+        // Every point of a triangular iris lies within the triangle.
+        let iris = Aperture::polygon(3, 90.0).unwrap();
+        let mut rng = StdRng::seed_from_u64(6);
+        for _ in 0..1000 {
+            let (x, y) = iris.sample(rng.gen(), rng.gen());
+            assert!(x * x + y * y <= 1.0 + 1e-5);
+            assert!(y >= -0.5 - 1e-5, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn equirectangular_images_are_twice_as_wide_as_tall() {
+        assert!(error("projection equirectangular", 1.5).contains("twice as wide"));
+        let camera = parse("projection equirectangular", 2.0).unwrap();
+        // The left and right edges look straight back.
+        for s in [0.0, 1.0] {
+            assert!(ray(camera.as_ref(), s, 0.5).unwrap().direction.unit_vector().z() > 0.9999);
+        }
+    }
+
+    #[test]
+    fn fisheye_corners_are_outside_the_image_circle() {
+        let camera = parse("projection fisheye\nfov 180", 1.5).unwrap();
+        for (s, t) in [(0.0, 0.0), (1.0, 1.0), (0.05, 0.95)] {
+            assert!(ray(camera.as_ref(), s, t).is_none());
+        }
+        // The rim of the circle is 90° off axis.
+        let rim = ray(camera.as_ref(), 1.0, 0.5).unwrap().direction.unit_vector();
+        assert!(rim.z().abs() < 1e-4 && rim.x() > 0.9999, "{:?}", rim);
+    }
+}
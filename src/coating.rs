@@ -0,0 +1,163 @@
+use rand::Rng;
+
+use crate::{hittable::HitRecord, material::{Material, beer_lambert}, microfacet::{Ggx, fresnel_dielectric}, onb::Onb, ray::Ray, vec3::{Color, Vec3, reflect, refract}};
+
+/// A thin dielectric layer over another material: car paint, varnish,
+/// lacquered wood. Light either reflects off the rough coat or refracts in
+/// through its mean surface, scatters once off the base and refracts back
+/// out. Light the underside of the coat reflects back down is dropped, which
+/// keeps the layered BSDF in closed form so `eval` and `pdf` can describe
+/// exactly what `scatter` samples.
+#[derive(Debug, Clone, Copy)]
+pub struct Coating {
+    pub ir: f32,
+    pub roughness: f32,
+    /// Beer–Lambert coefficient inside the coat.
+    pub absorption: Color,
+    pub thickness: f32,
+}
+
+impl Coating {
+    pub fn clear(ir: f32, roughness: f32) -> Self {
+        Coating { ir, roughness, absorption: Vec3::default(), thickness: 0.0 }
+    }
+
+    pub fn scatter(
+        &self,
+        base: &Material,
+        ray_in: &Ray,
+        rec: &HitRecord,
+        rng: &mut impl Rng,
+    ) -> Option<(Color, Ray)> {
+        // Seen from inside the object the coat doesn't exist.
+        if !rec.front_face {
+            return base.scatter(ray_in, rec, rng);
+        }
+
+        let uvw = Onb::from_w(rec.normal);
+        let ggx = Ggx::from_roughness(self.roughness, 0.0);
+        let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let reflectance = fresnel_dielectric(wo.z(), self.ir);
+        if reflectance > rng.gen() {
+            let m = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+            let wi = reflect(-wo, m);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let fresnel = fresnel_dielectric(wo.dot(m), self.ir) / reflectance;
+            return Some((Vec3::from(fresnel * ggx.g2(wo, wi) / ggx.g1(wo)), Ray::new(rec.p, uvw.local(wi))));
+        }
+
+        let down = self.enter(wo);
+        let base_rec = HitRecord { material: base, ..*rec };
+        let (attenuation, scattered) = base.scatter(&Ray::new(rec.p, uvw.local(down)), &base_rec, rng)?;
+        let weight = self.transmittance(down) * attenuation;
+
+        let up = uvw.to_local(Vec3::unit_vector(scattered.direction));
+        if up.z() <= 0.0 {
+            // The base let the light through; the coat is only crossed once.
+            return Some((weight, scattered));
+        }
+        // Leaving through the smooth underside of the coat.
+        let escaping = 1.0 - fresnel_dielectric(up.z(), 1.0 / self.ir);
+        if escaping <= 0.0 {
+            return None;
+        }
+        let wi = refract(up, Vec3(0.0, 0.0, -1.0), self.ir);
+        Some((escaping * weight * self.transmittance(up), Ray::new(rec.p, uvw.local(wi))))
+    }
+
+    /// The layered BSDF times the cosine towards `wi`, or `None` when the
+    /// base has no closed form.
+    pub fn eval(&self, base: &Material, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Option<Color> {
+        self.evaluate(base, ray_in, rec, wi).map(|(f, _)| f)
+    }
+
+    /// Solid-angle density with which `scatter` picks `wi`.
+    pub fn pdf(&self, base: &Material, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Option<f32> {
+        self.evaluate(base, ray_in, rec, wi).map(|(_, pdf)| pdf)
+    }
+
+    fn evaluate(&self, base: &Material, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Option<(Color, f32)> {
+        if !rec.front_face {
+            return Some((base.eval(ray_in, rec, wi)?, base.pdf(ray_in, rec, wi)?));
+        }
+
+        let uvw = Onb::from_w(rec.normal);
+        let ggx = Ggx::from_roughness(self.roughness, 0.0);
+        let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+        let local_wi = uvw.to_local(wi);
+        if wo.z() <= 0.0 {
+            return Some((Vec3::default(), 0.0));
+        }
+
+        let mut f = Vec3::default();
+        let mut pdf = 0.0;
+        let reflectance = fresnel_dielectric(wo.z(), self.ir);
+        if let Some((m, lobe, lobe_pdf)) = ggx.reflection(wo, local_wi) {
+            f = Vec3::from(fresnel_dielectric(wo.dot(m), self.ir) * lobe);
+            pdf = reflectance * lobe_pdf;
+        }
+
+        // The base sees the refracted view direction, and directions above
+        // the surface through the coat's mean plane.
+        let down = self.enter(wo);
+        let base_ray = Ray::new(rec.p, uvw.local(down));
+        let base_rec = HitRecord { material: base, ..*rec };
+        let (inner, compression, escaping) = if local_wi.z() > 0.0 {
+            let up = -self.enter(local_wi);
+            let escaping = 1.0 - fresnel_dielectric(local_wi.z(), self.ir);
+            // Solid angle inside the coat per solid angle outside.
+            let compression = local_wi.z() / (self.ir * self.ir * up.z());
+            (up, compression, escaping * self.transmittance(up))
+        } else {
+            (local_wi, 1.0, Vec3::from(1.0))
+        };
+        let inner = uvw.local(inner);
+        let base_f = base.eval(&base_ray, &base_rec, inner)?;
+        let base_pdf = base.pdf(&base_ray, &base_rec, inner)?;
+
+        let entering = 1.0 - reflectance;
+        f = f + (entering * compression) * (self.transmittance(down) * escaping * base_f);
+        pdf += entering * compression * base_pdf;
+        Some((f, pdf))
+    }
+
+    /// Local direction a ray arriving from `wo` continues in below the
+    /// coat's mean surface.
+    fn enter(&self, wo: Vec3) -> Vec3 {
+        refract(-wo, Vec3(0.0, 0.0, 1.0), 1.0 / self.ir)
+    }
+
+    /// Absorption along one crossing of the coat in local direction `w`.
+    fn transmittance(&self, w: Vec3) -> Color {
+        if self.thickness <= 0.0 {
+            return Vec3::from(1.0);
+        }
+        beer_lambert(self.absorption, self.thickness / w.z().abs().max(1e-4))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::tests::check_consistency;
+
+    #[test]
+    fn eval_matches_scatter() {
+        let varnish = Coating { ir: 1.5, roughness: 0.2, absorption: Vec3(0.9, 0.7, 0.4), thickness: 0.1 };
+        let paint = Material::coated(varnish, Material::Lambertian { albedo: Vec3(0.2, 0.4, 0.8) });
+        check_consistency(&paint, true);
+
+        let lacquered_metal = Material::coated(Coating::clear(1.5, 0.4), Material::metal(Vec3(0.9, 0.8, 0.6), 0.5));
+        check_consistency(&lacquered_metal, true);
+
+        let coated_glass = Material::coated(Coating::clear(1.3, 0.3), Material::rough_dielectric(1.5, 0.5));
+        check_consistency(&coated_glass, true);
+        check_consistency(&coated_glass, false);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{f32::consts::PI, io, path::Path, sync::Arc};
+
+use crate::{sky::Sky, sampling::Distribution2D, texture::ImageTexture, vec3::{Color, Point3, Vec3}};
+
+/// What rays that escape the scene see.
+#[derive(Debug, Clone, Default)]
+pub enum Background {
+    /// The white-to-blue sky of the original tracer.
+    #[default]
+    Gradient,
+    Environment(Arc<EnvironmentMap>),
+    /// Analytic daylight with a sun.
+    Sky(Arc<Sky>),
+}
+
+impl Background {
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        match self {
+            Background::Gradient => {
+                let unit_direction = Vec3::unit_vector(direction);
+                let t = 0.5 * (unit_direction.y() + 1.0);
+                (1.0-t) * Vec3(1.0,1.0,1.0) + t * Vec3(0.5, 0.7, 1.0)
+            }
+            Background::Environment(env) => env.radiance(direction),
+            Background::Sky(sky) => sky.radiance(direction),
+        }
+    }
+
+    /// Whether `sample` is available, so light sampling should be used.
+    pub fn importance_sampled(&self) -> bool {
+        matches!(self, Background::Environment(_) | Background::Sky(_))
+    }
+
+    /// Picks a direction towards the background for next-event estimation,
+    /// returning it with its radiance and solid-angle density. `None` when
+    /// the background isn't worth sampling explicitly.
+    pub fn sample(&self, u1: f32, u2: f32) -> Option<(Vec3, Color, f32)> {
+        match self {
+            Background::Gradient => None,
+            Background::Environment(env) => env.sample(u1, u2),
+            Background::Sky(sky) => sky.sample_sun(u1, u2),
+        }
+    }
+
+    /// Density with which `sample` would pick `direction`.
+    pub fn pdf(&self, direction: Vec3) -> Option<f32> {
+        match self {
+            Background::Gradient => None,
+            Background::Environment(env) => Some(env.pdf(direction)),
+            Background::Sky(sky) => Some(sky.sun_pdf(direction)),
+        }
+    }
+}
+
+/// Image-based lighting from an equirectangular (latitude-longitude) image,
+/// importance-sampled by luminance.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    image: ImageTexture,
+    /// Turn about the vertical axis, in radians.
+    rotation: f32,
+    intensity: f32,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: ImageTexture, rotation_degrees: f32, intensity: f32) -> Self {
+        let (width, height) = (image.width(), image.height());
+        // Rows near the poles cover less of the sphere.
+        let weights: Vec<f32> = (0..height)
+            .flat_map(|y| {
+                let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+                let image = &image;
+                (0..width).map(move |x| luminance(image.texel(x, y)) * sin_theta)
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, width);
+
+        EnvironmentMap {
+            image,
+            rotation: rotation_degrees.to_radians(),
+            intensity,
+            distribution,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>, rotation_degrees: f32, intensity: f32) -> io::Result<Self> {
+        Ok(EnvironmentMap::new(ImageTexture::load_hdr(path)?, rotation_degrees, intensity))
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let (x, y) = self.image_coords(direction.unit_vector());
+        self.intensity * self.texel_at(x, y)
+    }
+
+    pub fn sample(&self, u1: f32, u2: f32) -> Option<(Vec3, Color, f32)> {
+        let ((x, y), pdf_image) = self.distribution.sample(u1, u2);
+        let theta = PI * y;
+        let sin_theta = theta.sin();
+        if pdf_image <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        let direction = self.direction_at(x, y);
+        let pdf = pdf_image / (2.0 * PI * PI * sin_theta);
+        Some((direction, self.intensity * self.texel_at(x, y), pdf))
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let (x, y) = self.image_coords(direction.unit_vector());
+        let sin_theta = (PI * y).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(x, y) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn texel_at(&self, x: f32, y: f32) -> Color {
+        let w = self.image.width();
+        let h = self.image.height();
+        let px = ((x * w as f32) as usize).min(w - 1);
+        let py = ((y * h as f32) as usize).min(h - 1);
+        self.image.texel(px, py)
+    }
+
+    /// Image coordinates in `[0, 1]`, `y` running from the zenith down.
+    fn image_coords(&self, d: Vec3) -> (f32, f32) {
+        let phi = d.x().atan2(-d.z()) - self.rotation;
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        ((phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI)
+    }
+
+    fn direction_at(&self, x: f32, y: f32) -> Vec3 {
+        let phi = (x - 0.5) * 2.0 * PI + self.rotation;
+        let theta = y * PI;
+        Vec3(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+}
+
+/// A window-like opening, spanning `u` and `v` from `corner`, through which
+/// an environment map lights an interior. Portals aren't geometry: they only
+/// steer which directions the environment is sampled in.
+#[derive(Debug, Clone, Copy)]
+pub struct Portal {
+    pub corner: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+}
+
+impl Portal {
+    pub fn area(&self) -> f32 {
+        self.u.cross(self.v).length()
+    }
+
+    pub fn point_at(&self, s: f32, t: f32) -> Point3 {
+        self.corner + s * self.u + t * self.v
+    }
+
+    /// Solid-angle density, from `origin`, of the point of the portal that
+    /// `direction` passes through when its points are picked with `area_pdf`.
+    pub fn pdf(&self, origin: Point3, direction: Vec3, area_pdf: f32) -> Option<f32> {
+        let n = self.u.cross(self.v);
+        let d = direction.unit_vector();
+        let denom = d.dot(n);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.corner - origin).dot(n) / denom;
+        if t <= 0.0 {
+            return None;
+        }
+        // Coordinates of the crossing in the (u, v) frame.
+        let q = origin + t * d - self.corner;
+        let n2 = n.length_squared();
+        let s = q.cross(self.v).dot(n) / n2;
+        let r = self.u.cross(q).dot(n) / n2;
+        if !(0.0..=1.0).contains(&s) || !(0.0..=1.0).contains(&r) {
+            return None;
+        }
+        let cos = denom.abs() / n2.sqrt();
+        Some(area_pdf * t * t / cos)
+    }
+}
+
+#[inline]
+pub fn luminance(c: Color) -> f32 {
+    c.dot(Vec3(0.2126, 0.7152, 0.0722))
+}
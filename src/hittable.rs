@@ -2,17 +2,36 @@ use std::ops::Range;
 
 use crate::{material::Material, ray::Ray, vec3::{Point3, Vec3}};
 
+#[derive(Clone, Copy)]
 pub struct HitRecord<'m> {
     pub p: Point3,
+    /// Shading normal, facing against the incoming ray. Normal and bump maps
+    /// tilt this one.
     pub normal: Vec3,
+    /// True surface normal, on the same side as `normal`.
+    pub geometric_normal: Vec3,
+    /// Partial derivatives of the position along `u` and `v`, giving the
+    /// tangent frame for normal maps and anisotropy.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub material: &'m Material,
     pub t: f32,
+    /// Surface parameterisation for texture lookups.
+    pub u: f32,
+    pub v: f32,
+    /// Which triangle, or other part, of the object was hit.
+    pub primitive: usize,
+    /// Index of the object among the scene's, filled in by the list.
+    pub object: usize,
+    /// The object itself, filled in by the list, for materials that trace
+    /// rays through its interior.
+    pub surface: Option<&'m dyn Hittable>,
     pub front_face: bool,
 }
 
 pub trait Hittable: std::fmt::Debug + Sync + Send {
     fn hit<'o>(
-        &'o self, 
+        &'o self,
         ray: &Ray,
         t_range: Range<f32>,
         rng: &mut dyn FnMut() -> f32,
@@ -21,11 +40,22 @@ pub trait Hittable: std::fmt::Debug + Sync + Send {
 
 impl Hittable for Box<dyn Hittable> {
     fn hit<'o>(
-        &'o self, 
+        &'o self,
         ray: &Ray,
         t_range: Range<f32>,
         rng: &mut dyn FnMut() -> f32,
     ) -> Option<HitRecord<'o>> {
         (**self).hit(ray, t_range, rng)
     }
-}
\ No newline at end of file
+}
+
+impl<H: Hittable> Hittable for std::sync::Arc<H> {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f32>,
+        rng: &mut dyn FnMut() -> f32,
+    ) -> Option<HitRecord<'o>> {
+        (**self).hit(ray, t_range, rng)
+    }
+}
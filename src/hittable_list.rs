@@ -10,7 +10,7 @@ pub trait HittableList: Send + Sync {
     fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<HitRecord<'a>>;
 }
 
-impl<'r, T: HittableList + ?Sized> HittableList for &'r T {
+impl<T: HittableList + ?Sized> HittableList for &T {
     fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<HitRecord<'a>> {
         (*self).hit_top(ray, rng)
     }
@@ -19,13 +19,13 @@ impl<'r, T: HittableList + ?Sized> HittableList for &'r T {
 impl HittableList for Vec<Box<dyn Hittable>> {
     fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<HitRecord<'a>> {
         const NEAR: f32 = 0.001;
-        let mut nearest = std::f32::INFINITY;
+        let mut nearest = f32::INFINITY;
         let mut hit_record = None;
 
-        for obj in self {
+        for (object, obj) in self.iter().enumerate() {
             if let Some(rec) = obj.hit(ray, NEAR..nearest, &mut || rng.gen()) {
                 nearest = rec.t;
-                hit_record = Some(rec);
+                hit_record = Some(HitRecord { object, surface: Some(&**obj), ..rec });
             }
         }
 
@@ -0,0 +1,255 @@
+use std::{fs, io, path::Path};
+
+use crate::{onb::Onb, vec3::Vec3};
+
+/// Luminous intensity distribution of a fixture from an IESNA LM-63
+/// photometric file. Only type C photometry is supported, the kind used for
+/// nearly all architectural fixtures: vertical angles run from the nadir at
+/// 0° to the zenith at 180°, horizontal angles around the fixture's axis.
+#[derive(Debug, Clone)]
+pub struct IesProfile {
+    vertical_angles: Vec<f32>,
+    horizontal_angles: Vec<f32>,
+    /// One row of vertical samples per horizontal angle, in candela with the
+    /// file's multipliers applied.
+    candela: Vec<f32>,
+}
+
+impl IesProfile {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        IesProfile::parse(&text)
+    }
+
+    /// Parses the text of an LM-63-1995 or -2002 file. Tilt data is read
+    /// past but not applied, since the fixture is assumed mounted as
+    /// measured.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mut lines = text.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => break line.trim()[5..].trim().to_owned(),
+                Some(_) => continue,
+                None => return Err(invalid("IES file has no TILT line")),
+            }
+        };
+
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest
+            .iter()
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f32>());
+        let mut next = || -> io::Result<f32> {
+            numbers
+                .next()
+                .ok_or_else(|| invalid("IES file ends early"))?
+                .map_err(|_| invalid("IES file has a malformed number"))
+        };
+
+        if tilt == "INCLUDE" {
+            let _lamp_to_luminaire = next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()? as i32;
+        let _units = next()?;
+        let (_width, _length, _height) = (next()?, next()?, next()?);
+        let ballast_factor = next()?;
+        // "Future use" in LM-63-2002, the ballast-lamp factor before that.
+        let ballast_lamp_factor = next()?;
+        let _input_watts = next()?;
+
+        if photometric_type != 1 {
+            return Err(invalid("only type C IES photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid("IES file has no candela values"));
+        }
+
+        let vertical_angles = (0..vertical_count).map(|_| next()).collect::<io::Result<Vec<_>>>()?;
+        let horizontal_angles = (0..horizontal_count).map(|_| next()).collect::<io::Result<Vec<_>>>()?;
+        let scale = multiplier * ballast_factor * ballast_lamp_factor;
+        let count = vertical_count
+            .checked_mul(horizontal_count)
+            .ok_or_else(|| invalid("IES file has too many candela values"))?;
+        let candela = (0..count)
+            .map(|_| next().map(|c| scale * c.max(0.0)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let ascending = |angles: &[f32]| angles.windows(2).all(|w| w[0] < w[1]);
+        if !ascending(&vertical_angles) || !ascending(&horizontal_angles) {
+            return Err(invalid("IES angles must be increasing"));
+        }
+
+        Ok(IesProfile { vertical_angles, horizontal_angles, candela })
+    }
+
+    /// Candela emitted along `direction`, given in the fixture's frame:
+    /// `w` pointing at the nadir and `u` along horizontal angle 0°.
+    /// Horizontal angles run counterclockwise seen from above the fixture,
+    /// which in the right-handed frame is from `u` away from `v`.
+    pub fn intensity(&self, frame: &Onb, direction: Vec3) -> f32 {
+        let local = frame.to_local(direction.unit_vector());
+        let vertical = local.z().clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = (-local.y()).atan2(local.x()).to_degrees().rem_euclid(360.0);
+        self.intensity_at(vertical, horizontal)
+    }
+
+    /// Candela at a vertical and horizontal angle in degrees, interpolated
+    /// bilinearly. Directions outside the measured vertical range get none.
+    pub fn intensity_at(&self, vertical: f32, horizontal: f32) -> f32 {
+        let (first, last) = (self.vertical_angles[0], self.vertical_angles[self.vertical_angles.len() - 1]);
+        if vertical < first || vertical > last {
+            return 0.0;
+        }
+        let (v0, v1, fv) = bracket(&self.vertical_angles, vertical);
+        let (h0, h1, fh) = bracket(&self.horizontal_angles, self.fold_horizontal(horizontal));
+
+        let n = self.vertical_angles.len();
+        let at = |h: usize, v: usize| self.candela[h * n + v];
+        let near = (1.0 - fv) * at(h0, v0) + fv * at(h0, v1);
+        let far = (1.0 - fv) * at(h1, v0) + fv * at(h1, v1);
+        (1.0 - fh) * near + fh * far
+    }
+
+    /// Highest candela value in the file.
+    pub fn max_intensity(&self) -> f32 {
+        self.candela.iter().copied().fold(0.0, f32::max)
+    }
+
+    /// Candela averaged over the whole sphere of directions, so that the
+    /// fixture's flux is `4π` times this.
+    pub fn average_intensity(&self) -> f32 {
+        const STEPS: usize = 64;
+        let (mut sum, mut weight) = (0.0, 0.0);
+        for i in 0..STEPS {
+            let vertical = (i as f32 + 0.5) / STEPS as f32 * 180.0;
+            let sin = vertical.to_radians().sin();
+            for j in 0..2 * STEPS {
+                let horizontal = (j as f32 + 0.5) / (2 * STEPS) as f32 * 360.0;
+                sum += sin * self.intensity_at(vertical, horizontal);
+                weight += sin;
+            }
+        }
+        sum / weight
+    }
+
+    /// Maps a horizontal angle onto the part of the circle the file covers,
+    /// using the symmetry its last horizontal angle implies.
+    fn fold_horizontal(&self, angle: f32) -> f32 {
+        let angles = &self.horizontal_angles;
+        let last = angles[angles.len() - 1];
+        if angles.len() == 1 {
+            return angles[0];
+        }
+        let mut a = angle.rem_euclid(360.0);
+        if last <= 90.0 {
+            // Quadrant symmetry.
+            if a > 180.0 {
+                a = 360.0 - a;
+            }
+            if a > 90.0 {
+                a = 180.0 - a;
+            }
+        } else if last <= 180.0 {
+            // Bilateral symmetry about the 0–180° plane.
+            if a > 180.0 {
+                a = 360.0 - a;
+            }
+        } else if angles[0] >= 90.0 && !(90.0..=270.0).contains(&a) {
+            // Bilateral about the 90–270° plane, stored from 90° to 270°.
+            a = if a < 90.0 { 180.0 - a } else { 540.0 - a };
+        }
+        a
+    }
+}
+
+/// Neighbouring indices of `x` in increasing `angles` and the fraction
+/// between them, clamped at the ends.
+fn bracket(angles: &[f32], x: f32) -> (usize, usize, f32) {
+    let i = angles.partition_point(|&a| a <= x);
+    if i == 0 {
+        return (0, 0, 0.0);
+    }
+    if i == angles.len() {
+        return (i - 1, i - 1, 0.0);
+    }
+    let (a0, a1) = (angles[i - 1], angles[i]);
+    (i - 1, i, (x - a0) / (a1 - a0))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A fixture that shines 100 cd towards horizontal 0°, 200 towards 90°,
+    /// 300 towards 180° and 400 towards 270°, the same at every vertical
+    /// angle, with a multiplier of 2.
+    pub(crate) const ASYMMETRIC: &str = "IESNA:LM-63-2002
+[TEST] asymmetric
+TILT=NONE
+1 -1 2 3 5 1 2 0.1 0.1 0
+1.0 1.0 50
+0 45 90
+0, 90, 180, 270, 360
+50 50 50
+100 100 100
+150 150 150
+200 200 200
+50 50 50
+";
+
+    fn header(counts: &str) -> String {
+        format!("TILT=NONE\n1 -1 1 {} 2 0 0 0\n1 1 0\n", counts)
+    }
+
+    #[test]
+    fn reads_candela_with_multipliers() {
+        let ies = IesProfile::parse(ASYMMETRIC).unwrap();
+        assert_eq!(ies.intensity_at(45.0, 90.0), 200.0);
+        assert_eq!(ies.intensity_at(10.0, 135.0), 250.0);
+        assert_eq!(ies.max_intensity(), 400.0);
+        // Beyond the measured vertical range.
+        assert_eq!(ies.intensity_at(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn horizontal_angles_turn_counterclockwise_from_above() {
+        let ies = IesProfile::parse(ASYMMETRIC).unwrap();
+        // Hanging down the y axis with 0° along x. Seen from above, with x
+        // to the right, counterclockwise turns x towards -z.
+        let frame = Onb::from_w_u(Vec3(0.0, -1.0, 0.0), Vec3(1.0, 0.0, 0.0));
+        let towards = |x: f32, z: f32| ies.intensity(&frame, Vec3(x, -1.0, z));
+        assert!((towards(1.0, 0.0) - 100.0).abs() < 1e-3);
+        assert!((towards(0.0, -1.0) - 200.0).abs() < 1e-3);
+        assert!((towards(-1.0, 0.0) - 300.0).abs() < 1e-3);
+        assert!((towards(0.0, 1.0) - 400.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(IesProfile::parse(&(header("1 1 1") + "0\n0\n5\n")).is_ok());
+        for text in [
+            String::from("1 1 1 1 1 1 1 0 0 0\n1 1 0\n0\n0\n1\n"),
+            header("1 1 2") + "0\n0\n1\n",
+            header("0 1 1"),
+            header("1e10 1e10 1"),
+            header("2 1 1") + "0 90\n0\n1\n",
+            header("2 1 1") + "90 0\n0\n1 1\n",
+            header("1 1 1") + "0\n0\nbright\n",
+        ] {
+            assert!(IesProfile::parse(&text).is_err(), "{}", text);
+        }
+    }
+}
@@ -1,15 +1,40 @@
 
+pub mod sky;
 pub mod sphere;
+pub mod aabb;
+pub mod area_light;
+pub mod bsdf;
+pub mod bvh;
 pub mod color;
 pub mod hittable_list;
 pub mod utility_funcs;
 pub mod camera;
+pub mod coating;
+pub mod environment;
 pub mod material;
+pub mod merl;
+pub mod mesh;
+pub mod microfacet;
+pub mod node_graph;
+pub mod normal_map;
+pub mod npr;
+pub mod onb;
+pub mod principled;
 pub mod hittable;
+pub mod ies;
+pub mod light;
+pub mod light_tree;
 pub mod ray;
+pub mod sampling;
+pub mod scene;
+pub mod texture;
 pub mod vec3;
 
+use hittable::HitRecord;
 use hittable_list::HittableList;
+use light::Light;
+use material::Material;
+use scene::{LightId, ObjectId, Scene};
 use rand::prelude::*;
 use rayon::prelude::*;
 use crate::ray::Ray;
@@ -40,21 +65,289 @@ use crate::vec3::*;
 //     Vec3::default()
 // }
 
-pub fn ray_color(world: &impl HittableList, r: Ray, depth:i32, rng: &mut impl Rng) -> Color {
+pub fn ray_color(scene: &Scene, r: Ray, depth:i32, rng: &mut impl Rng) -> Color {
+    trace(scene, r, depth, None, rng)
+}
+
+/// One camera sample split into layers for compositing CG over a
+/// photograph. Sum samples and resolve them with `rgba`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Layers {
+    /// Light from the CG objects; the background is left for the plate.
+    pub color: Color,
+    /// How much of the sample the CG objects covered.
+    pub alpha: f32,
+    /// How much of it landed on a shadow catcher.
+    pub catcher: f32,
+    /// Light reaching a white catcher with the CG objects in place...
+    pub lit: Color,
+    /// ...and with nothing but the lights.
+    pub unlit: Color,
+}
+
+impl std::ops::Add for Layers {
+    type Output = Layers;
+
+    fn add(self, other: Layers) -> Layers {
+        Layers {
+            color: self.color + other.color,
+            alpha: self.alpha + other.alpha,
+            catcher: self.catcher + other.catcher,
+            lit: self.lit + other.lit,
+            unlit: self.unlit + other.unlit,
+        }
+    }
+}
+
+impl Layers {
+    /// Premultiplied colour and alpha from `samples` summed samples. Over
+    /// shadow catchers the result is a black shadow as opaque as the share
+    /// of light the objects block, plus whatever light they bounce there.
+    pub fn rgba(&self, samples: f32) -> (Color, f32) {
+        let mut color = self.color / samples;
+        let mut alpha = self.alpha / samples;
+        let unlit = environment::luminance(self.unlit);
+        if self.catcher > 0.0 && unlit > 0.0 {
+            let coverage = self.catcher / samples;
+            let shadow = (1.0 - environment::luminance(self.lit) / unlit).clamp(0.0, 1.0);
+            let bounce = (self.lit - self.unlit).map(|c| c.max(0.0)) / self.catcher;
+            color = color + coverage * bounce;
+            alpha += coverage * shadow;
+        }
+        (color, alpha)
+    }
+}
+
+/// Like `ray_color`, but for compositing: misses are transparent and
+/// shadow catchers record what the scene does to them.
+pub fn ray_layers(scene: &Scene, r: Ray, depth: i32, rng: &mut impl Rng) -> Layers {
+    match scene.nearest(&r, true, rng) {
+        None => Layers::default(),
+        Some(rec) if matches!(rec.material, Material::ShadowCatcher) => {
+            let white = Material::Lambertian { albedo: Vec3(1.0,1.0,1.0) };
+            let rec = HitRecord { material: &white, ..rec };
+            Layers {
+                catcher: 1.0,
+                lit: shade(scene, &r, &rec, depth, rng),
+                unlit: unshadowed(scene, &r, &rec, rng),
+                ..Layers::default()
+            }
+        }
+        Some(rec) => Layers {
+            color: rec.material.emitted(&r, &rec) + shade(scene, &r, &rec, depth, rng),
+            alpha: 1.0,
+            ..Layers::default()
+        },
+    }
+}
+
+/// The surface a ray was scattered from.
+#[derive(Debug, Clone, Copy)]
+struct Bounce {
+    /// Its object, for light linking.
+    object: ObjectId,
+    /// Density with which it picked the ray, if it also sampled the lights
+    /// explicitly; light the ray finds, from the background or an emissive
+    /// object, is then weighted against the light sample by multiple
+    /// importance sampling.
+    bsdf_pdf: Option<f32>,
+}
+
+fn trace(scene: &Scene, r: Ray, depth:i32, from: Option<Bounce>, rng: &mut impl Rng) -> Color {
     if depth <= 0 {return Vec3(0.0,0.0,0.0)}
 
-    if let Some(rec) = world.hit_top(&r, rng) {
-        if let Some((attenuation, scattered)) = rec.material.scatter( &r, &rec, rng) {
-            let testing = attenuation * ray_color(world,scattered, depth-1, rng);
-            return testing
+    if let Some(rec) = scene.hit_top(&r, rng) {
+        let mut emitted = rec.material.emitted(&r, &rec);
+        if let (Some(from), Some(light)) = (from, scene.light_at(&rec)) {
+            if !scene.illuminates(light, from.object) {
+                emitted = Vec3(0.0,0.0,0.0);
+            } else if let (Some(bsdf_pdf), Some(light_pdf)) = (from.bsdf_pdf, scene.light_pdf(&r, &rec)) {
+                emitted = power_heuristic(bsdf_pdf, light_pdf) * emitted;
+            }
         }
 
+        let mut color = emitted + shade(scene, &r, &rec, depth, rng);
+        if let Some(from) = from.filter(|_| scene.is_shadowless(&rec)) {
+            color = color + light_behind(scene, &r, from, rng);
+        }
+        return color;
+    }
+
+    let radiance = scene.background.radiance(r.direction);
+    match (from.and_then(|f| f.bsdf_pdf), scene.background_pdf(&r)) {
+        (Some(bsdf_pdf), Some(light_pdf)) => power_heuristic(bsdf_pdf, light_pdf) * radiance,
+        _ => radiance,
+    }
+}
+
+/// Light sampled directly from the scene finds the lights behind shadowless
+/// objects, so their share of what a ray bounced off `from` would have
+/// found past them is added here, or the two weights wouldn't sum to one.
+fn light_behind(scene: &Scene, r: &Ray, from: Bounce, rng: &mut impl Rng) -> Color {
+    let bsdf_pdf = match from.bsdf_pdf {
+        Some(pdf) => pdf,
+        None => return Vec3(0.0,0.0,0.0),
+    };
+    match scene.nearest_occluder(r, rng) {
+        Some(rec) => match (scene.light_at(&rec), scene.light_pdf(r, &rec)) {
+            (Some(light), Some(light_pdf)) if scene.illuminates(light, from.object) => {
+                power_heuristic(bsdf_pdf, light_pdf) * rec.material.emitted(r, &rec)
+            }
+            _ => Vec3(0.0,0.0,0.0),
+        },
+        None => match scene.background_pdf(r) {
+            Some(light_pdf) => power_heuristic(bsdf_pdf, light_pdf) * scene.background.radiance(r.direction),
+            None => Vec3(0.0,0.0,0.0),
+        },
+    }
+}
+
+/// Light `rec` scatters back along `r`: sampled directly from the lights,
+/// plus one bounce followed further.
+fn shade(scene: &Scene, r: &Ray, rec: &HitRecord, depth: i32, rng: &mut impl Rng) -> Color {
+    let material = rec.material.at_hit(r, rec);
+    let rec = &HitRecord { material: &material, ..*rec };
+    let (attenuation, scattered) = match rec.material.scatter(r, rec, rng) {
+        Some(scattered) => scattered,
+        None => return Vec3(0.0,0.0,0.0),
+    };
+    if leaks_through_surface(rec, &scattered) {
         return Vec3(0.0,0.0,0.0);
     }
 
-    let unit_direction = Vec3::unit_vector(r.direction);
-    let t = 0.5 * (unit_direction.y() + 1.0);
-    return (1.0-t) * Vec3(1.0,1.0,1.0) + t * Vec3(0.5, 0.7, 1.0);
+    let wi = Vec3::unit_vector(scattered.direction);
+    let pdf = rec.material.pdf(r, rec, wi);
+    let mut direct = sample_lights(scene, r, rec, rng);
+    if pdf.is_some() && scene.background.importance_sampled() {
+        direct = direct + sample_background(scene, r, rec, rng);
+    }
+
+    let bounce = Bounce { object: scene.object_at(rec), bsdf_pdf: pdf };
+    direct + attenuation * trace(scene, scattered, depth-1, Some(bounce), rng)
+}
+
+/// What `shade` would give at `rec` in a scene emptied of everything but
+/// its lights: nothing casts shadows and only light sources are seen.
+fn unshadowed(scene: &Scene, r: &Ray, rec: &HitRecord, rng: &mut impl Rng) -> Color {
+    let eval = |wi: Vec3| {
+        if splits_normals(rec, wi) { Vec3(0.0,0.0,0.0) } else { rec.material.eval(r, rec, wi).unwrap_or_default() }
+    };
+
+    let mut total = Vec3(0.0,0.0,0.0);
+    for (i, light) in scene.lights().iter().enumerate() {
+        if matches!(light, Light::Directional { .. }) && scene.illuminates(LightId::Light(i), scene.object_at(rec)) {
+            if let Some(sample) = light.sample(rec.p) {
+                total = total + eval(sample.wi) * sample.li;
+            }
+        }
+    }
+    if let Some(sample) = scene.sample_light(rec.p, rng) {
+        if sample.pdf > 0.0 && scene.illuminates(sample.light, scene.object_at(rec)) {
+            total = total + (1.0 / sample.pdf) * eval(sample.wi) * sample.radiance;
+        }
+    }
+
+    // The background, from both strategies as `shade` does but unoccluded.
+    if scene.background.importance_sampled() {
+        if let Some((wi, radiance, light_pdf)) = scene.sample_background(rec.p, rng) {
+            let bsdf_pdf = rec.material.pdf(r, rec, wi).unwrap_or(0.0);
+            if light_pdf > 0.0 {
+                total = total + (power_heuristic(light_pdf, bsdf_pdf) / light_pdf) * eval(wi) * radiance;
+            }
+        }
+    }
+    if let Some((attenuation, scattered)) = rec.material.scatter(r, rec, rng) {
+        let radiance = scene.background.radiance(scattered.direction);
+        let weight = match (rec.material.pdf(r, rec, scattered.direction.unit_vector()), scene.background_pdf(&scattered)) {
+            (Some(bsdf_pdf), Some(light_pdf)) => power_heuristic(bsdf_pdf, light_pdf),
+            _ => 1.0,
+        };
+        total = total + weight * attenuation * radiance;
+    }
+    total
+}
+
+/// Direct light from every directional light and from one other light the
+/// scene picks for this point. Area lights are weighted against BSDF
+/// sampling with the power heuristic; point lights nothing else can find.
+/// Every material with a non-specular lobe can `eval` it; behind a
+/// perfectly smooth interface only `scatter` finds area lights, and point
+/// lights give no light at all, as with a real mirror.
+fn sample_lights(scene: &Scene, ray_in: &Ray, rec: &HitRecord, rng: &mut impl Rng) -> Color {
+    let mut total = Vec3(0.0,0.0,0.0);
+    for (i, light) in scene.lights().iter().enumerate() {
+        if !matches!(light, Light::Directional { .. }) || !scene.illuminates(LightId::Light(i), scene.object_at(rec)) {
+            continue;
+        }
+        if let Some(sample) = light.sample(rec.p) {
+            total = total + unoccluded_bsdf(scene, ray_in, rec, sample.wi, sample.distance, rng) * sample.li;
+        }
+    }
+
+    let sample = match scene.sample_light(rec.p, rng) {
+        Some(sample) if sample.pdf > 0.0 && !sample.radiance.near_zero() && scene.illuminates(sample.light, scene.object_at(rec)) => sample,
+        _ => return total,
+    };
+    let f = unoccluded_bsdf(scene, ray_in, rec, sample.wi, sample.distance, rng);
+    if sample.delta {
+        return total + (1.0 / sample.pdf) * f * sample.radiance;
+    }
+    let bsdf_pdf = rec.material.pdf(ray_in, rec, sample.wi).unwrap_or(0.0);
+    total + (power_heuristic(sample.pdf, bsdf_pdf) / sample.pdf) * f * sample.radiance
+}
+
+/// BSDF times cosine towards a light `distance` away along `wi`, or nothing
+/// if the shading normal would leak it through the surface or something is
+/// in the way. Light from behind still reaches transmissive materials.
+fn unoccluded_bsdf(scene: &Scene, ray_in: &Ray, rec: &HitRecord, wi: Vec3, distance: f32, rng: &mut impl Rng) -> Color {
+    if splits_normals(rec, wi) {
+        return Vec3(0.0,0.0,0.0);
+    }
+    let f = rec.material.eval(ray_in, rec, wi).unwrap_or_default();
+    if f.near_zero() || scene.occluded(&Ray::new(rec.p, wi), distance * (1.0 - 1e-4), rng) {
+        return Vec3(0.0,0.0,0.0);
+    }
+    f
+}
+
+/// Next-event estimation towards the background, weighted against BSDF
+/// sampling with the power heuristic.
+fn sample_background(scene: &Scene, ray_in: &Ray, rec: &HitRecord, rng: &mut impl Rng) -> Color {
+    let (wi, radiance, light_pdf) = match scene.sample_background(rec.p, rng) {
+        Some(sample) => sample,
+        None => return Vec3(0.0,0.0,0.0),
+    };
+    if light_pdf <= 0.0 || splits_normals(rec, wi) {
+        return Vec3(0.0,0.0,0.0);
+    }
+
+    let f = rec.material.eval(ray_in, rec, wi).unwrap_or_default();
+    if f.near_zero() || scene.occluded(&Ray::new(rec.p, wi), f32::INFINITY, rng) {
+        return Vec3(0.0,0.0,0.0);
+    }
+
+    let bsdf_pdf = rec.material.pdf(ray_in, rec, wi).unwrap_or(0.0);
+    (power_heuristic(light_pdf, bsdf_pdf) / light_pdf) * f * radiance
+}
+
+/// Weight for a sample taken with density `a` when `b` could also have
+/// produced it.
+#[inline]
+fn power_heuristic(a: f32, b: f32) -> f32 {
+    let (a2, b2) = (a * a, b * b);
+    if a2 + b2 == 0.0 { 0.0 } else { a2 / (a2 + b2) }
+}
+
+/// Whether a tilted shading normal sent light through the real surface.
+/// Rays that start elsewhere, such as volumetric events inside the object,
+/// aren't bound by either normal.
+fn leaks_through_surface(rec: &HitRecord, scattered: &Ray) -> bool {
+    (scattered.origin - rec.p).near_zero() && splits_normals(rec, scattered.direction)
+}
+
+/// Whether `wi` is on different sides of the shading and the true normal.
+fn splits_normals(rec: &HitRecord, wi: Vec3) -> bool {
+    (wi.dot(rec.normal) > 0.0) != (wi.dot(rec.geometric_normal) > 0.0)
 }
 
 pub struct Image(Vec<Vec<Vec3>>);
@@ -70,6 +363,11 @@ impl Image {
         )
     }
 
+    /// The pixels, top row first.
+    pub fn rows(&self) -> &[Vec<Vec3>] {
+        &self.0
+    }
+
     pub fn compute(nx: usize, ny: usize, mut f: impl FnMut(usize, usize) -> Vec3) -> Image {
         Image(
             (0..ny)
@@ -78,4 +376,95 @@ impl Image {
                 .collect(),
         )
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{coating::Coating, mesh::TriangleMesh, node_graph::{Graph, GraphMaterial}, principled::Principled, scene::LightLink, sphere::Sphere};
+    use rand::rngs::StdRng;
+    use std::{collections::HashSet, sync::Arc};
+
+    /// Light reaching the camera straight off a unit sphere of `material`,
+    /// without any bounces, with and without a point light shining on it.
+    fn lit_by_point_light(material: Material) -> (Color, Color) {
+        let sphere = || -> Vec<Box<dyn hittable::Hittable>> { vec![Box::new(Sphere::new(Vec3::default(), 1.0, material.clone()))] };
+        let light = Light::Point { position: Vec3(0.0, 3.0, 3.0), intensity: Vec3::from(10.0), profile: None };
+        let lit = Scene::new(sphere()).with_light(light);
+        let dark = Scene::new(sphere());
+
+        let ray = Ray::new(Vec3(0.0, 0.0, 5.0), Vec3(0.0, 0.0, -1.0));
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut render = |scene: &Scene| (0..64).fold(Vec3::default(), |sum, _| sum + ray_color(scene, ray, 1, &mut rng));
+        (render(&lit), render(&dark))
+    }
+
+    #[test]
+    fn point_lights_reach_every_glossy_material() {
+        let graph = GraphMaterial::new(Graph::new(), Principled::default());
+        for material in [
+            Material::Principled(Principled::default()),
+            Material::Principled(Principled { metallic: 1.0, transmission: 0.0, ..Principled::default() }),
+            Material::coated(Coating::clear(1.5, 0.1), Material::Lambertian { albedo: Vec3::from(0.5) }),
+            Material::Graph(Arc::new(graph)),
+            Material::rough_dielectric(1.5, 0.5),
+        ] {
+            let (lit, dark) = lit_by_point_light(material);
+            assert!(dark.near_zero());
+            assert!(lit.x() > 0.0 && lit.y() > 0.0 && lit.z() > 0.0, "{:?}", lit);
+        }
+    }
+
+    /// A floor under a wide ceiling panel, inside a black room so nothing
+    /// but the panel lights it. Returns the scene with the floor's handle.
+    fn lamp_over_floor() -> (Scene, ObjectId) {
+        let black = Material::Lambertian { albedo: Vec3::default() };
+        let mut scene = Scene::new(vec![]);
+        let floor = scene.add_object(TriangleMesh::quad(
+            Vec3(-5.0, 0.0, 5.0),
+            Vec3(10.0, 0.0, 0.0),
+            Vec3(0.0, 0.0, -10.0),
+            Material::Lambertian { albedo: Vec3::from(0.5) },
+        ));
+        scene.add_emitter(TriangleMesh::quad(
+            Vec3(-2.0, 1.5, -2.0),
+            Vec3(4.0, 0.0, 0.0),
+            Vec3(0.0, 0.0, 4.0),
+            Material::diffuse_light(Vec3::from(1.0), 1.0),
+        ));
+        scene.add_object(Sphere::new(Vec3::default(), 50.0, black));
+        (scene, floor)
+    }
+
+    fn floor_radiance(scene: &Scene) -> Color {
+        let ray = Ray::new(Vec3(0.0, 1.0, 3.0), Vec3(0.0, -1.0, -3.0));
+        let mut rng = StdRng::seed_from_u64(11);
+        let n = 40_000;
+        (1.0 / n as f32) * (0..n).fold(Vec3::default(), |sum, _| sum + ray_color(scene, ray, 2, &mut rng))
+    }
+
+    #[test]
+    fn shadowless_objects_leave_the_lighting_alone() {
+        let (open, _) = lamp_over_floor();
+        let (mut blocked, _) = lamp_over_floor();
+        let blocker = blocked.add_object(Sphere::new(Vec3(0.0, 0.8, 0.0), 0.5, Material::Lambertian { albedo: Vec3::default() }));
+        let blocked = blocked.with_shadowless(blocker);
+
+        let (open, blocked) = (floor_radiance(&open), floor_radiance(&blocked));
+        assert!(open.x() > 0.0);
+        assert!((open.x() - blocked.x()).abs() < 0.02 * open.x(), "{:?} vs {:?}", open, blocked);
+    }
+
+    #[test]
+    fn light_links_follow_their_objects() {
+        let (scene, floor) = lamp_over_floor();
+        let from_above = Ray::new(Vec3(0.0, 5.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        let lamp = scene.light_at(&scene.hit_top(&from_above, &mut StdRng::seed_from_u64(1)).unwrap()).unwrap();
+        // Objects added after the link don't change which object it names;
+        // this one is outside the room, out of the way.
+        let mut scene = scene.with_light_link(lamp, LightLink::Exclude(HashSet::from([floor])));
+        scene.add_object(Sphere::new(Vec3(0.0, 0.0, 100.0), 0.5, Material::Lambertian { albedo: Vec3::from(0.5) }));
+        assert!(floor_radiance(&scene).near_zero());
+    }
+}
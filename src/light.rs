@@ -0,0 +1,199 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use crate::{aabb::Aabb, environment::luminance, ies::IesProfile, light_tree::LightBounds, onb::Onb, vec3::{Color, Point3, Vec3}};
+
+/// Lights with no geometry. Rays can never hit them, so they only reach the
+/// image through shadow rays cast from each shading point.
+#[derive(Debug, Clone)]
+pub enum Light {
+    /// Radiates `intensity` equally in all directions, or shaped by a
+    /// photometric `profile` hanging straight down.
+    Point { position: Point3, intensity: Color, profile: Option<Photometry> },
+    /// A point light restricted to a cone around `direction`: full strength
+    /// inside `inner_angle`, fading smoothly to nothing at `outer_angle`
+    /// (half-angles, in degrees). A `profile` is aimed along `direction`
+    /// and still cut off by the cone. `direction` need not be unit length.
+    Spot {
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        inner_angle: f32,
+        outer_angle: f32,
+        profile: Option<Photometry>,
+    },
+    /// Parallel light such as the sun, `direction` pointing from the scene
+    /// towards the light and `irradiance` measured head-on.
+    Directional { direction: Vec3, irradiance: Color },
+}
+
+/// A measured distribution mounted on a light, turned so that the profile's
+/// horizontal angle 0° points along `zero_azimuth`.
+#[derive(Debug, Clone)]
+pub struct Photometry {
+    pub profile: Arc<IesProfile>,
+    pub zero_azimuth: Vec3,
+}
+
+/// Light arriving at a point from one light.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    /// Unit vector from the shading point towards the light.
+    pub wi: Vec3,
+    /// Radiance already integrated over the light, so it only needs the BSDF.
+    pub li: Color,
+    /// Distance to the light along `wi`, infinite for directional lights.
+    pub distance: f32,
+}
+
+impl Light {
+    pub fn spot(position: Point3, target: Point3, intensity: Color, inner_angle: f32, outer_angle: f32) -> Self {
+        Light::Spot {
+            position,
+            direction: (target - position).unit_vector(),
+            intensity,
+            inner_angle,
+            outer_angle,
+            profile: None,
+        }
+    }
+
+    /// Shapes a point or spot light by a measured distribution. `intensity`
+    /// then scales the profile's candela values, so white 1.0 renders the
+    /// fixture as measured. The profile's horizontal angle 0° points along
+    /// `zero_azimuth`, projected across the light's axis.
+    pub fn with_profile(mut self, ies: IesProfile, zero_azimuth: Vec3) -> Self {
+        match &mut self {
+            Light::Point { profile, .. } | Light::Spot { profile, .. } => {
+                *profile = Some(Photometry { profile: Arc::new(ies), zero_azimuth })
+            }
+            Light::Directional { .. } => {}
+        }
+        self
+    }
+
+    /// Position and rough power for choosing between lights; `None` for
+    /// directional lights, which are everywhere at once.
+    pub fn bounds(&self) -> Option<LightBounds> {
+        let (position, intensity, profile, solid_angle) = match self {
+            Light::Point { position, intensity, profile } => (position, intensity, profile, 4.0 * PI),
+            Light::Spot { position, intensity, outer_angle, profile, .. } => {
+                (position, intensity, profile, 2.0 * PI * (1.0 - outer_angle.to_radians().cos()))
+            }
+            Light::Directional { .. } => return None,
+        };
+        let shape = profile.as_ref().map_or(1.0, |p| p.profile.average_intensity());
+        Some(LightBounds {
+            bounds: Aabb::from_points([*position]),
+            power: solid_angle * shape * luminance(*intensity),
+        })
+    }
+
+    pub fn sample(&self, p: Point3) -> Option<LightSample> {
+        match self {
+            Light::Point { position, intensity, profile } => {
+                let (wi, distance) = towards(p, *position)?;
+                let shape = photometric(profile, Vec3(0.0, -1.0, 0.0), -wi);
+                if shape <= 0.0 {
+                    return None;
+                }
+                Some(LightSample { wi, li: (shape / (distance * distance)) * *intensity, distance })
+            }
+            Light::Spot { position, direction, intensity, inner_angle, outer_angle, profile } => {
+                let (wi, distance) = towards(p, *position)?;
+                let axis = direction.unit_vector();
+                let falloff = spot_falloff((-wi).dot(axis), *inner_angle, *outer_angle);
+                let shape = falloff * photometric(profile, axis, -wi);
+                if shape <= 0.0 {
+                    return None;
+                }
+                Some(LightSample { wi, li: (shape / (distance * distance)) * *intensity, distance })
+            }
+            Light::Directional { direction, irradiance } => Some(LightSample {
+                wi: direction.unit_vector(),
+                li: *irradiance,
+                distance: f32::INFINITY,
+            }),
+        }
+    }
+}
+
+fn towards(p: Point3, position: Point3) -> Option<(Vec3, f32)> {
+    let d = position - p;
+    let distance = d.length();
+    if distance <= 0.0 {
+        return None;
+    }
+    Some((d / distance, distance))
+}
+
+/// Candela of `photometry` along `outgoing` with its nadir at `axis`, or 1.0
+/// without one.
+fn photometric(photometry: &Option<Photometry>, axis: Vec3, outgoing: Vec3) -> f32 {
+    match photometry {
+        Some(Photometry { profile, zero_azimuth }) => profile.intensity(&Onb::from_w_u(axis, *zero_azimuth), outgoing),
+        None => 1.0,
+    }
+}
+
+/// Smoothstep from the outer to the inner cone.
+fn spot_falloff(cos_angle: f32, inner_angle: f32, outer_angle: f32) -> f32 {
+    let cos_inner = inner_angle.to_radians().cos();
+    let cos_outer = outer_angle.to_radians().cos();
+    if cos_angle >= cos_inner {
+        return 1.0;
+    }
+    if cos_angle <= cos_outer || cos_inner <= cos_outer {
+        return 0.0;
+    }
+    let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ies::tests::ASYMMETRIC;
+
+    #[test]
+    fn profiles_keep_their_zero_azimuth() {
+        let ies = IesProfile::parse(ASYMMETRIC).unwrap();
+        let candela = |light: &Light, p: Point3| {
+            let sample = light.sample(p).unwrap();
+            sample.li.x() * sample.distance * sample.distance
+        };
+
+        // Tilting the spot mustn't turn the profile around its axis.
+        for target in [Vec3(0.0, -1.0, 0.0), Vec3(0.02, -1.0, 0.0), Vec3(-0.3, -1.0, 0.2)] {
+            let spot = Light::spot(Vec3::default(), target, Vec3::from(1.0), 60.0, 80.0).with_profile(ies.clone(), Vec3(1.0, 0.0, 0.0));
+            let axis = target.unit_vector();
+            let zero = (Vec3(1.0, 0.0, 0.0) - axis.x() * axis).unit_vector();
+            // A quarter turn counterclockwise seen from behind the spot.
+            let quarter = (-axis).cross(zero);
+            assert!((candela(&spot, axis + 0.2 * zero) - 100.0).abs() < 0.5);
+            assert!((candela(&spot, axis + 0.2 * quarter) - 200.0).abs() < 0.5);
+        }
+
+        let point = Light::Point { position: Vec3::default(), intensity: Vec3::from(1.0), profile: None }
+            .with_profile(ies, Vec3(0.0, 0.0, 1.0));
+        assert!((candela(&point, Vec3(0.0, -1.0, 1.0)) - 100.0).abs() < 0.5);
+        assert!((candela(&point, Vec3(1.0, -1.0, 0.0)) - 200.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn spot_cones_ignore_the_direction_length() {
+        let spot = |direction: Vec3| Light::Spot {
+            position: Vec3::default(),
+            direction,
+            intensity: Vec3::from(1.0),
+            inner_angle: 20.0,
+            outer_angle: 40.0,
+            profile: None,
+        };
+        for p in [Vec3(0.0, -1.0, 0.0), Vec3(0.5, -1.0, 0.0), Vec3(0.7, -1.0, 0.0), Vec3(1.0, -1.0, 0.0)] {
+            let li = |light: Light| light.sample(p).map_or(0.0, |sample| sample.li.x());
+            let unit = li(spot(Vec3(0.0, -1.0, 0.0)));
+            assert_eq!(unit, li(spot(Vec3(0.0, -5.0, 0.0))));
+            assert_eq!(unit, li(spot(Vec3(0.0, -0.2, 0.0))));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{aabb::{Aabb, component}, vec3::Point3};
+
+/// Where a light is and how much it gives off, as far as choosing between
+/// lights is concerned.
+#[derive(Debug, Clone, Copy)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    /// Total emitted power; only its size relative to other lights matters.
+    pub power: f32,
+}
+
+/// Hierarchy over a scene's lights for picking one per shading point. Each
+/// step down the tree chooses a child in proportion to its power over its
+/// squared distance, so a point near a few dim lights still finds them among
+/// hundreds far away, in logarithmic time.
+#[derive(Debug, Clone, Default)]
+pub struct LightTree {
+    nodes: Vec<Node>,
+    /// Leaf node of each light.
+    leaves: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    power: f32,
+    parent: Option<usize>,
+    /// For leaves the light's index, for interior nodes the index of the
+    /// second child; the first child always follows its parent.
+    offset: usize,
+    leaf: bool,
+}
+
+impl LightTree {
+    pub fn build(lights: &[LightBounds]) -> Self {
+        let mut tree = LightTree {
+            nodes: Vec::with_capacity(2 * lights.len()),
+            leaves: vec![0; lights.len()],
+        };
+        let mut indices: Vec<usize> = (0..lights.len()).collect();
+        if !lights.is_empty() {
+            tree.build_node(lights, &mut indices, None);
+        }
+        tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn build_node(&mut self, lights: &[LightBounds], indices: &mut [usize], parent: Option<usize>) -> usize {
+        let bounds = indices.iter().fold(Aabb::empty(), |b, &i| b.union(lights[i].bounds));
+        let power = indices.iter().map(|&i| lights[i].power.max(0.0)).sum();
+        let index = self.nodes.len();
+        self.nodes.push(Node { bounds, power, parent, offset: 0, leaf: false });
+
+        if let [light] = *indices {
+            self.nodes[index].offset = light;
+            self.nodes[index].leaf = true;
+            self.leaves[light] = index;
+            return index;
+        }
+
+        // Median split along the axis where the centroids spread the most.
+        let centroids = Aabb::from_points(indices.iter().map(|&i| lights[i].bounds.centroid()));
+        let axis = centroids.major_axis();
+        let mid = indices.len() / 2;
+        indices.select_nth_unstable_by(mid, |&a, &b| {
+            component(lights[a].bounds.centroid(), axis)
+                .partial_cmp(&component(lights[b].bounds.centroid(), axis))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let (first, second) = indices.split_at_mut(mid);
+        self.build_node(lights, first, Some(index));
+        let second = self.build_node(lights, second, Some(index));
+        self.nodes[index].offset = second;
+        index
+    }
+
+    /// Picks a light for shading point `p`, returning it with the
+    /// probability of having picked it.
+    pub fn sample(&self, p: Point3, mut u: f32) -> Option<(usize, f32)> {
+        let mut n = 0;
+        let mut probability = 1.0;
+        loop {
+            let node = self.nodes.get(n)?;
+            if node.leaf {
+                return Some((node.offset, probability));
+            }
+            let p_first = self.first_child_probability(n, p);
+            if u < p_first {
+                u /= p_first;
+                probability *= p_first;
+                n += 1;
+            } else {
+                u = ((u - p_first) / (1.0 - p_first)).min(0.999_999);
+                probability *= 1.0 - p_first;
+                n = node.offset;
+            }
+            if probability <= 0.0 {
+                return None;
+            }
+        }
+    }
+
+    /// Probability that `sample` picks `light` for shading point `p`.
+    pub fn probability(&self, p: Point3, light: usize) -> f32 {
+        let mut probability = 1.0;
+        let mut child = self.leaves[light];
+        while let Some(parent) = self.nodes[child].parent {
+            let p_first = self.first_child_probability(parent, p);
+            probability *= if child == parent + 1 { p_first } else { 1.0 - p_first };
+            child = parent;
+        }
+        probability
+    }
+
+    fn first_child_probability(&self, n: usize, p: Point3) -> f32 {
+        let first = self.importance(n + 1, p);
+        let second = self.importance(self.nodes[n].offset, p);
+        if first + second > 0.0 { first / (first + second) } else { 0.5 }
+    }
+
+    /// Power over squared distance to the centre, the distance kept above
+    /// the node's own size so that points among its lights don't blow up.
+    fn importance(&self, n: usize, p: Point3) -> f32 {
+        let node = &self.nodes[n];
+        let distance_squared = (p - node.bounds.centroid()).length_squared();
+        let size_squared = 0.25 * node.bounds.extent().length_squared();
+        let d2 = distance_squared.max(size_squared).max(1e-8);
+        node.power / d2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    fn light(x: f32, y: f32, z: f32, power: f32) -> LightBounds {
+        let p = Vec3(x, y, z);
+        LightBounds { bounds: Aabb::from_points([p, p + Vec3::from(0.1)]), power }
+    }
+
+    #[test]
+    fn sampling_matches_probability() {
+        let lights = [
+            light(0.0, 0.0, 0.0, 1.0),
+            light(5.0, 0.0, 0.0, 10.0),
+            light(0.0, 3.0, -2.0, 0.5),
+            light(-4.0, 1.0, 1.0, 0.0),
+            light(2.0, 2.0, 2.0, 3.0),
+            light(9.0, -1.0, 0.0, 50.0),
+            light(0.5, 0.2, 0.1, 2.0),
+        ];
+        let tree = LightTree::build(&lights);
+
+        for &p in &[Vec3(0.0, 0.0, 0.5), Vec3(6.0, 1.0, 0.0), Vec3(-10.0, 5.0, 3.0)] {
+            let total: f32 = (0..lights.len()).map(|i| tree.probability(p, i)).sum();
+            assert!((total - 1.0).abs() < 1e-5);
+            assert_eq!(tree.probability(p, 3), 0.0);
+
+            let n = 20_000;
+            let mut counts = vec![0; lights.len()];
+            for k in 0..n {
+                let (i, probability) = tree.sample(p, (k as f32 + 0.5) / n as f32).unwrap();
+                assert!((probability - tree.probability(p, i)).abs() < 1e-5);
+                counts[i] += 1;
+            }
+            for (i, &count) in counts.iter().enumerate() {
+                let expected = tree.probability(p, i) * n as f32;
+                assert!((count as f32 - expected).abs() <= 2.0 + 1e-3 * n as f32, "light {}: {} vs {}", i, count, expected);
+            }
+        }
+    }
+}
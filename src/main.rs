@@ -1,9 +1,14 @@
-use rust_ray_tracer::{camera::Camera, hittable::Hittable};
+use rust_ray_tracer::{camera::{self, Camera, Projection}, hittable::Hittable};
 use rust_ray_tracer::vec3::Vec3;
 use rust_ray_tracer::*;
 
 use color::write_color;
 use material::Material;
+use npr::Toon;
+use environment::{Background, EnvironmentMap};
+use scene::Scene;
+use sky::Sky;
+use std::sync::Arc;
 use vec3::*;
 use sphere::Sphere;
 
@@ -45,7 +50,7 @@ fn random_scene(rng:&mut impl Rng) -> Vec<Box<dyn Hittable>> {
                         Sphere::new(
                             center, 
                             0.2, 
-                            Material::Metal {albedo, fuzz})
+                            Material::metal(albedo, fuzz))
                         )
                     )
                 } else {
@@ -53,7 +58,7 @@ fn random_scene(rng:&mut impl Rng) -> Vec<Box<dyn Hittable>> {
                         Sphere::new(
                             center,
                             0.2,
-                            Material::Dielectric {ir: 1.5})
+                            Material::Dielectric {ir: 1.5, absorption: Vec3::default()})
                     ))
                 }
             }
@@ -64,7 +69,7 @@ fn random_scene(rng:&mut impl Rng) -> Vec<Box<dyn Hittable>> {
         Sphere::new(
             Vec3(0.0, 1.0, 0.0), 
             1.0, 
-        Material::Dielectric {ir: 1.5})
+        Material::Dielectric {ir: 1.5, absorption: Vec3::default()})
     ));
     world.push(Box::new(
         Sphere::new(
@@ -76,7 +81,7 @@ fn random_scene(rng:&mut impl Rng) -> Vec<Box<dyn Hittable>> {
         Sphere::new(
             Vec3(4.0, 1.0, 0.0), 
             1.0, 
-        Material::Metal {albedo: Vec3(0.7, 0.6, 0.5), fuzz:0.0})
+        Material::metal(Vec3(0.7, 0.6, 0.5), 0.0))
     ));
 
     world
@@ -88,8 +93,8 @@ fn test_reflection() -> Vec<Box<dyn Hittable>> {
 
     let ground_material = Material::Lambertian {albedo: Vec3(0.4, 1.0, 0.7)};
     let material_center = Material::Lambertian {albedo: Vec3(0.7, 0.7, 1.0)};
-    let material_left = Material::Dielectric {ir:1.5};
-    let material_right = Material::Metal {albedo: Vec3(1.0,0.2,0.87), fuzz:0.0};
+    let material_left = Material::Dielectric {ir: 1.5, absorption: Vec3::default()};
+    let material_right = Material::metal(Vec3(1.0,0.2,0.87), 0.0);
 
     world.push(Box::new(Sphere::new(
         Vec3(0.0, -100.5, -1.0), 
@@ -125,22 +130,71 @@ fn main() {
 
     //World
     let mut rng = thread_rng();
-    let world = random_scene(&mut rng);
+    let mut world = Scene::new(random_scene(&mut rng));
+    if let Some(path) = std::env::args().find(|arg| arg.ends_with(".hdr")) {
+        let env = EnvironmentMap::load(path, 0.0, 1.0).expect("couldn't load environment map");
+        world.background = Background::Environment(Arc::new(env));
+    } else if std::env::args().any(|arg| arg == "--sky") {
+        world.background = Background::Sky(Arc::new(Sky::new(35.0, 120.0, 3.0)));
+    }
     // let world = test_reflection();
 
     let lookfrom:Point3 = Vec3(13.0, 2.0, 3.0);
     let lookat:Point3 = Vec3(0.0, 0.0, 0.0);
     let vup = Vec3(0.0, 1.0, 0.0);
     let dist_to_focus = 20.0;
-    let aperture = 0.1;
+    // Depth of field would blur the toon renderer's edge detection.
+    let toon = std::env::args().any(|arg| arg == "--toon");
+    let aperture = if toon { 0.0 } else { 0.1 };
     //Camera
 
-    let cam = Camera::new(lookfrom, lookat, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus);
+    let cam: Box<dyn Projection> = match std::env::args().skip_while(|arg| arg != "--camera").nth(1) {
+        Some(path) => {
+            let source = std::fs::read_to_string(path).expect("couldn't read camera description");
+            camera::parse(&source, ASPECT_RATIO).unwrap_or_else(|e| panic!("bad camera description: {}", e))
+        }
+        None => Box::new(Camera::new(lookfrom, lookat, vup, 20.0, ASPECT_RATIO, aperture, dist_to_focus)),
+    };
 
     //Render
 
     println!("P3\n{} {}\n255", width, height);
 
+    if toon {
+        let image = Toon::default().render(&world, cam.as_ref(), width as usize, height as usize, &mut rng);
+        for row in image.rows() {
+            for &pixel in row {
+                write_color(pixel, 1.0);
+            }
+        }
+        return;
+    }
+
+    // Premultiplied colour for compositing, with coverage and caught
+    // shadows written to a greyscale matte.
+    if let Some(path) = std::env::args().skip_while(|arg| arg != "--alpha").nth(1) {
+        let mut matte = format!("P2\n{} {}\n255\n", width, height);
+        for j in (0..height).rev() {
+            eprint!("\rScanlines remaining: {} ", j);
+            for i in 0..width {
+                let mut layers = Layers::default();
+                for _ in 0..SAMPLES_PER_PIXEL {
+                    let u = (i as f32 + rng.gen::<f32>()) / (width as f32 -1.0);
+                    let v = (j as f32 + rng.gen::<f32>()) / (height as f32-1.0);
+                    if let Some(r) = cam.ray(u,v, &mut rng) {
+                        layers = layers + ray_layers(&world, r, 50, &mut rng);
+                    }
+                }
+                let (color, alpha) = layers.rgba(SAMPLES_PER_PIXEL as f32);
+                write_color(color, 1.0);
+                matte += &format!("{}\n", (256.0 * alpha.clamp(0.0, 0.999)) as u32);
+            }
+        }
+        std::fs::write(path, matte).expect("couldn't write alpha matte");
+        eprintln!("\nDone.");
+        return;
+    }
+
     // for (r,g,b) in screen {
     //     println!("{} {} {}", r,g,b);
     // }
@@ -152,8 +206,9 @@ fn main() {
             for _ in 0..SAMPLES_PER_PIXEL {
                 let u = (i as f32 + rng.gen::<f32>()) / (width as f32 -1.0);
                 let v = (j as f32 + rng.gen::<f32>()) / (height as f32-1.0);
-                let r = cam.get_ray(u,v, &mut rng);
-                pixel_color = pixel_color + ray_color(&world, r, 50,  &mut rng);
+                if let Some(r) = cam.ray(u,v, &mut rng) {
+                    pixel_color = pixel_color + ray_color(&world, r, 50,  &mut rng);
+                }
             }
             write_color(pixel_color, SAMPLES_PER_PIXEL as f32);
         }
@@ -1,25 +1,118 @@
-use rand::{Rng, thread_rng};
+use std::{borrow::Cow, ops::Add, sync::Arc};
 
-use crate::{hittable::HitRecord, ray::Ray, vec3::{Color, Vec3, reflect, refract}};
+use rand::{Rng, RngCore};
+
+use crate::{bsdf::Bsdf, coating::Coating, hittable::{HitRecord, Hittable}, merl::MerlBrdf, microfacet::{Ggx, charlie_d, conductor_from_reflectivity, fresnel_conductor, fresnel_dielectric, fresnel_thin_film, sheen_visibility}, node_graph::GraphMaterial, onb::Onb, principled::Principled, ray::Ray, texture::Texture, vec3::{Color, Vec3, reflect, refract}};
 
 // cargo build --release && ./target/release/rust_ray_tracing.exe > image.ppm && emulsion image.ppm
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum Material {
     Lambertian { albedo: Vec3 },
-    Metal { albedo: Vec3, fuzz: f32 },
-    Dielectric { ir: f32}
+    /// GGX microfacet conductor with complex index of refraction `eta + ik`.
+    /// `anisotropy` in `[-1, 1]` stretches the highlight along `dpdu`, the
+    /// direction texture coordinate `u` increases in.
+    Metal { eta: Color, k: Color, roughness: f32, anisotropy: f32 },
+    /// `absorption` is the Beer–Lambert coefficient per unit of distance
+    /// travelled inside the object; zero gives perfectly clear glass.
+    Dielectric { ir: f32, absorption: Color },
+    /// Frosted glass: GGX reflection and transmission (Walter et al. 2007).
+    RoughDielectric { ir: f32, roughness: f32, absorption: Color },
+    Principled(Principled),
+    /// `base` seen through a dielectric `coating`.
+    Coated { coating: Coating, base: Arc<Material> },
+    /// Random-walk subsurface scattering beneath a smooth dielectric boundary.
+    /// `albedo` is the colour the material settles to once light has diffused
+    /// through it and `mean_free_path` how far light travels, per channel,
+    /// between scattering events. The whole walk happens inside `scatter`,
+    /// which needs `rec.surface` to trace it; without one the object acts
+    /// as clear glass.
+    Subsurface { ir: f32, albedo: Color, mean_free_path: Color },
+    /// A smooth interface carrying an interference film. `thickness` is read
+    /// in `[0, 1]`, as image textures give it, and mapped onto `min_nm` to
+    /// `max_nm` nanometres. With `substrate_ir` of 1.0 the object is a film
+    /// in air, a soap bubble, and light passes through undeviated.
+    ThinFilm { film_ir: f32, substrate_ir: f32, thickness: Texture, min_nm: f32, max_nm: f32 },
+    /// Fabric: a Charlie sheen lobe over a diffuse base, for velvet and satin.
+    Cloth { albedo: Color, sheen: Color, sheen_roughness: f32 },
+    /// `base` with holes cut by an opacity texture: leaves, fences, decals.
+    Masked { opacity: Texture, mode: AlphaMode, base: Arc<Material> },
+    /// A measured MERL BRDF, sampled by cosine.
+    Measured(Arc<MerlBrdf>),
+    /// Principled inputs driven by a node graph instead of constants.
+    Graph(Arc<GraphMaterial>),
+    /// A material implemented outside this crate.
+    Custom(Arc<dyn Bsdf>),
+    /// Emits `intensity` times `emission` and reflects nothing. One-sided
+    /// lights only shine from their front face.
+    DiffuseLight { emission: Texture, intensity: f32, two_sided: bool },
+    /// Stand-in for the ground of a photograph that CG is composited onto.
+    /// Ordinary rays pass through it; only the alpha-aware output sees it,
+    /// recording the shadows and bounce light the scene casts on it.
+    ShadowCatcher,
+}
+
+/// How an opacity texture decides whether a hit counts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    /// Hits with opacity below the threshold pass straight through.
+    Cutoff(f32),
+    /// Hits are kept with probability equal to the opacity, which resolves
+    /// to partial transparency once enough samples are taken.
+    Stochastic,
 }
 
 impl Material {
+    /// A dielectric whose interior fades to `color` after travelling
+    /// `distance` units, which is easier to art-direct than a raw coefficient.
+    pub fn tinted_dielectric(ir: f32, color: Color, distance: f32) -> Self {
+        Material::Dielectric {
+            ir,
+            absorption: absorption_from_color(color, distance),
+        }
+    }
+
+    /// A conductor that reflects `albedo` head-on, for when measured data
+    /// isn't at hand.
+    pub fn metal(albedo: Color, roughness: f32) -> Self {
+        let (eta, k) = conductor_from_reflectivity(albedo, albedo);
+        Material::Metal { eta, k, roughness, anisotropy: 0.0 }
+    }
+
+    pub fn rough_dielectric(ir: f32, roughness: f32) -> Self {
+        Material::RoughDielectric { ir, roughness, absorption: Vec3::default() }
+    }
+
+    pub fn coated(coating: Coating, base: Material) -> Self {
+        Material::Coated { coating, base: Arc::new(base) }
+    }
+
+    /// A film of even `thickness` nanometres.
+    pub fn thin_film(film_ir: f32, thickness: f32, substrate_ir: f32) -> Self {
+        Material::ThinFilm { film_ir, substrate_ir, thickness: 1.0.into(), min_nm: 0.0, max_nm: thickness }
+    }
+
+    pub fn masked(opacity: Texture, mode: AlphaMode, base: Material) -> Self {
+        Material::Masked { opacity, mode, base: Arc::new(base) }
+    }
+
+    pub fn diffuse_light(emission: Color, intensity: f32) -> Self {
+        Material::DiffuseLight { emission: emission.into(), intensity, two_sided: false }
+    }
+
+    pub fn conductor(preset: Conductor, roughness: f32, anisotropy: f32) -> Self {
+        let (eta, k) = preset.ior();
+        Material::Metal { eta, k, roughness, anisotropy }
+    }
+
     pub fn scatter (
         &self,
         ray_in: &Ray,
         rec: &HitRecord,
         rng: &mut impl Rng,
     ) -> Option<(Color, Ray)> {
-        match &rec.material {
-            &Material::Lambertian { albedo } => {
+        match self {
+            Material::Lambertian { albedo } => {
                 let mut scatter_direction = rec.p + rec.normal + Vec3::random_unit_vector(rng);    
                 if scatter_direction.near_zero() {
                     scatter_direction = rec.normal;
@@ -32,41 +125,247 @@ impl Material {
         
                 Some((*albedo, scatterd))
             }
-            &Material::Metal { albedo, fuzz} => {
-                let f = if *fuzz < 1.0 {*fuzz} else {1.0};
-                let reflected = reflect(Vec3::unit_vector(ray_in.direction), rec.normal);
-                let scattered = Ray::new(rec.p, reflected + f * Vec3::random_in_unit_sphere(rng));
-        
-                if scattered.direction.dot(rec.normal) > 0.0 {
-                    return Some((*albedo,scattered))
+            Material::Metal { eta, k, roughness, anisotropy } => {
+                let uvw = tangent_frame(rec);
+                let ggx = Ggx::from_roughness(*roughness, *anisotropy);
+
+                let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+                if wo.z() <= 0.0 {
+                    return None;
+                }
+                let m = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+                let wi = reflect(-wo, m);
+                if wi.z() <= 0.0 {
+                    return None;
                 }
-                None
+
+                // With visible-normal sampling D and most of G cancel against the pdf.
+                let attenuation = (ggx.g2(wo, wi) / ggx.g1(wo)) * fresnel_conductor(wo.dot(m), *eta, *k);
+                Some((attenuation, Ray::new(rec.p, uvw.local(wi))))
             }
-            &Material::Dielectric { ir } => {
-                let mut rngg = thread_rng();
+            Material::Dielectric { ir, absorption } => {
+                let direction = smooth_boundary(*ir, ray_in, rec, rng);
+                let attenuation = interior_transmittance(*absorption, ray_in, rec);
+                Some((attenuation, Ray::new(rec.p, direction)))
+            }
+            Material::RoughDielectric { ir, roughness, absorption } => {
+                let uvw = Onb::from_w(rec.normal);
+                let ggx = Ggx::from_roughness(*roughness, 0.0);
                 let refraction_ratio = if rec.front_face {1.0/ *ir} else {*ir};
-    
+
+                let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+                if wo.z() <= 0.0 {
+                    return None;
+                }
+                let m = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+                let cos_o = wo.dot(m);
+
+                // Picking reflection with probability F cancels the Fresnel term,
+                // leaving the same G2/G1 weight for both lobes.
+                let wi = if fresnel_dielectric(cos_o, 1.0 / refraction_ratio) > rng.gen() {
+                    let wi = reflect(-wo, m);
+                    if wi.z() <= 0.0 {
+                        return None;
+                    }
+                    wi
+                } else {
+                    let wi = refract(-wo, m, refraction_ratio);
+                    if wi.z() >= 0.0 {
+                        return None;
+                    }
+                    wi
+                };
+
+                let attenuation = (ggx.g2(wo, wi) / ggx.g1(wo)) * interior_transmittance(*absorption, ray_in, rec);
+                Some((attenuation, Ray::new(rec.p, uvw.local(wi))))
+            }
+            Material::Principled(principled) => principled.scatter(ray_in, rec, rng),
+            Material::Coated { coating, base } => coating.scatter(base, ray_in, rec, rng),
+            Material::Subsurface { ir, albedo, mean_free_path } => {
+                let direction = smooth_boundary(*ir, ray_in, rec, rng);
+                match rec.surface {
+                    Some(surface) if rec.front_face && direction.dot(rec.normal) < 0.0 => {
+                        let walk = RandomWalk { ir: *ir, albedo: *albedo, mean_free_path: *mean_free_path };
+                        walk.exit(surface, Ray::new(rec.p, direction), rng)
+                    }
+                    _ => Some((Vec3::from(1.0), Ray::new(rec.p, direction))),
+                }
+            }
+            Material::ThinFilm { film_ir, substrate_ir, thickness, min_nm, max_nm } => {
+                let (n1, n3) = if rec.front_face {(1.0, *substrate_ir)} else {(*substrate_ir, 1.0)};
                 let unit_direction = Vec3::unit_vector(ray_in.direction);
                 let cos_theta = f32::min((-unit_direction).dot(rec.normal), 1.0);
-                let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
-    
-                let direction = match refraction_ratio * sin_theta > 1.0 || reflectance(cos_theta, refraction_ratio) > rngg.gen() {
-                    true => reflect(unit_direction, rec.normal),
-                    false => refract(unit_direction, rec.normal, refraction_ratio)
-                };
 
-                // let direction = reflect(unit_direction, rec.normal);
-    
-                // let direction = refract(ray_in.direction, rec.normal, refraction_ratio)
-                // .filter(|_| rng.gen::<f32>() >= reflectance(cos_theta, refraction_ratio))
-                // .unwrap_or_else(|| reflect(ray_in.direction, rec.normal));
-    
-                Some((Vec3(1.0,1.0,1.0), Ray::new(rec.p, direction)))
+                let d = min_nm + (max_nm - min_nm) * thickness.scalar(rec.u, rec.v, rec.p).clamp(0.0, 1.0);
+                let reflectance = fresnel_thin_film(cos_theta, n1, *film_ir, n3, d);
+                let p_reflect = reflectance.reduce(f32::add) / 3.0;
+
+                // Pick a branch by average reflectance and keep the spectral
+                // shape in the weight.
+                if p_reflect > rng.gen() {
+                    Some((reflectance / p_reflect, Ray::new(rec.p, reflect(unit_direction, rec.normal))))
+                } else {
+                    let direction = refract(unit_direction, rec.normal, n1 / n3);
+                    Some(((Vec3::from(1.0) - reflectance) / (1.0 - p_reflect), Ray::new(rec.p, direction)))
+                }
+            }
+            Material::Cloth { albedo, sheen, sheen_roughness } => {
+                let wo = -Vec3::unit_vector(ray_in.direction);
+                let cos_o = wo.dot(rec.normal);
+                if cos_o <= 0.0 {
+                    return None;
+                }
+
+                let mut direction = rec.normal + Vec3::random_unit_vector(rng);
+                if direction.near_zero() {
+                    direction = rec.normal;
+                }
+                let wi = direction.unit_vector();
+                let cos_i = wi.dot(rec.normal).max(0.0);
+                let cos_h = (wo + wi).unit_vector().dot(rec.normal);
+
+                // Cosine sampling leaves BRDF * pi as the weight. The base is
+                // dimmed by a rough fit of what the fibres reflect, which
+                // grows towards grazing angles.
+                let sheen_brdf = charlie_d(*sheen_roughness, cos_h) * sheen_visibility(cos_o, cos_i);
+                let base = 1.0 - sheen.reduce(f32::max) * 0.5 * (1.0 - cos_o).powi(2);
+                let attenuation = base * *albedo + (std::f32::consts::PI * sheen_brdf) * *sheen;
+                Some((attenuation, Ray::new(rec.p, wi)))
             }
+            Material::Masked { base, .. } => {
+                base.scatter(ray_in, &HitRecord { material: base, ..*rec }, rng)
+            }
+            Material::Measured(brdf) => {
+                let uvw = Onb::from_w(rec.normal);
+                let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+
+                let mut direction = rec.normal + Vec3::random_unit_vector(rng);
+                if direction.near_zero() {
+                    direction = rec.normal;
+                }
+                let wi = uvw.to_local(direction.unit_vector());
+
+                let attenuation = std::f32::consts::PI * brdf.eval(wo, wi);
+                Some((attenuation, Ray::new(rec.p, direction)))
+            }
+            Material::Graph(graph) => graph.scatter(ray_in, rec, rng),
+            Material::Custom(bsdf) => bsdf.scatter(ray_in, rec, rng),
+            Material::DiffuseLight { .. } | Material::ShadowCatcher => None,
         }
     }
 
-    pub fn emitted(&self, _p: Vec3) -> Vec3 {
+    /// Whether a hit at `(u, v)` falls in a hole of the opacity mask, and
+    /// should be skipped by the intersection routine.
+    pub fn is_cut_out(&self, u: f32, v: f32, p: Vec3, rng: &mut dyn FnMut() -> f32) -> bool {
+        match self {
+            Material::Masked { opacity, mode, .. } => {
+                let alpha = opacity.scalar(u, v, p);
+                match mode {
+                    AlphaMode::Cutoff(threshold) => alpha < *threshold,
+                    AlphaMode::Stochastic => alpha <= rng(),
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// The BSDF times the cosine towards `wi`, for light sampling. `None`
+    /// for materials behind a perfectly smooth interface, whose specular
+    /// directions can only be reached by `scatter`.
+    pub fn eval(&self, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Option<Color> {
+        let wo = -Vec3::unit_vector(ray_in.direction);
+        let cos_i = wi.dot(rec.normal);
+        match self {
+            Material::Lambertian { albedo } => {
+                Some(cos_i.max(0.0) / std::f32::consts::PI * *albedo)
+            }
+            Material::Metal { eta, k, roughness, anisotropy } => {
+                let uvw = tangent_frame(rec);
+                let ggx = Ggx::from_roughness(*roughness, *anisotropy);
+                let (wo, wi) = (uvw.to_local(wo), uvw.to_local(wi));
+                if wo.z() <= 0.0 || wi.z() <= 0.0 {
+                    return Some(Vec3::default());
+                }
+                let m = (wo + wi).unit_vector();
+                let f = fresnel_conductor(wo.dot(m), *eta, *k);
+                Some((ggx.d(m) * ggx.g2(wo, wi) / (4.0 * wo.z())) * f)
+            }
+            Material::Cloth { albedo, sheen, sheen_roughness } => {
+                let cos_o = wo.dot(rec.normal);
+                if cos_o <= 0.0 || cos_i <= 0.0 {
+                    return Some(Vec3::default());
+                }
+                let cos_h = (wo + wi).unit_vector().dot(rec.normal);
+                let sheen_brdf = charlie_d(*sheen_roughness, cos_h) * sheen_visibility(cos_o, cos_i);
+                let base = 1.0 - sheen.reduce(f32::max) * 0.5 * (1.0 - cos_o).powi(2);
+                Some(cos_i * (base / std::f32::consts::PI * *albedo + sheen_brdf * *sheen))
+            }
+            Material::Measured(brdf) => {
+                let uvw = Onb::from_w(rec.normal);
+                Some(cos_i.max(0.0) * brdf.eval(uvw.to_local(wo), uvw.to_local(wi)))
+            }
+            Material::RoughDielectric { ir, roughness, absorption } => {
+                let uvw = Onb::from_w(rec.normal);
+                let ggx = Ggx::from_roughness(*roughness, 0.0);
+                let eta = if rec.front_face {*ir} else {1.0/ *ir};
+                let (f, _) = rough_dielectric(&ggx, eta, uvw.to_local(wo), uvw.to_local(wi));
+                Some(f * interior_transmittance(*absorption, ray_in, rec))
+            }
+            Material::Principled(principled) => Some(principled.eval(ray_in, rec, wi)),
+            Material::Coated { coating, base } => coating.eval(base, ray_in, rec, wi),
+            Material::Masked { base, .. } => base.eval(ray_in, &HitRecord { material: base, ..*rec }, wi),
+            Material::Graph(graph) => Some(graph.eval(ray_in, rec, wi)),
+            Material::Custom(bsdf) => bsdf.eval(ray_in, rec, wi),
+            Material::Dielectric { .. } | Material::Subsurface { .. } | Material::ThinFilm { .. } => None,
+            Material::DiffuseLight { .. } | Material::ShadowCatcher => Some(Vec3::default()),
+        }
+    }
+
+    /// Solid-angle density with which `scatter` picks `wi`, wherever `eval`
+    /// is available.
+    pub fn pdf(&self, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Option<f32> {
+        match self {
+            Material::Lambertian { .. } | Material::Cloth { .. } | Material::Measured(_) => {
+                Some(wi.dot(rec.normal).max(0.0) / std::f32::consts::PI)
+            }
+            Material::Metal { roughness, anisotropy, .. } => {
+                let uvw = tangent_frame(rec);
+                let ggx = Ggx::from_roughness(*roughness, *anisotropy);
+                let (wo, wi) = (uvw.to_local(-Vec3::unit_vector(ray_in.direction)), uvw.to_local(wi));
+                if wo.z() <= 0.0 || wi.z() <= 0.0 {
+                    return Some(0.0);
+                }
+                let m = (wo + wi).unit_vector();
+                Some(ggx.g1(wo) * ggx.d(m) / (4.0 * wo.z()))
+            }
+            Material::RoughDielectric { ir, roughness, .. } => {
+                let uvw = Onb::from_w(rec.normal);
+                let ggx = Ggx::from_roughness(*roughness, 0.0);
+                let eta = if rec.front_face {*ir} else {1.0/ *ir};
+                let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+                Some(rough_dielectric(&ggx, eta, wo, uvw.to_local(wi)).1)
+            }
+            Material::Principled(principled) => Some(principled.pdf(ray_in, rec, wi)),
+            Material::Coated { coating, base } => coating.pdf(base, ray_in, rec, wi),
+            Material::Masked { base, .. } => base.pdf(ray_in, &HitRecord { material: base, ..*rec }, wi),
+            Material::Graph(graph) => Some(graph.pdf(ray_in, rec, wi)),
+            Material::Custom(bsdf) => bsdf.pdf(ray_in, rec, wi),
+            Material::Dielectric { .. } | Material::Subsurface { .. } | Material::ThinFilm { .. } => None,
+            Material::DiffuseLight { .. } | Material::ShadowCatcher => Some(0.0),
+        }
+    }
+
+    /// This material as it stands at `rec`. A graph material becomes the
+    /// principled material its graph gives there, so a shading point runs
+    /// the graph once however often it is then scattered and evaluated.
+    pub fn at_hit(&self, ray_in: &Ray, rec: &HitRecord) -> Cow<'_, Material> {
+        match self {
+            Material::Graph(graph) => Cow::Owned(Material::Principled(graph.at(ray_in, rec))),
+            _ => Cow::Borrowed(self),
+        }
+    }
+
+    pub fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Vec3 {
         // match self {
         //     Material::DiffuseLight {
         //         emission,
@@ -75,20 +374,372 @@ impl Material {
         //     _ => Vec3::default(),
         // }
 
-        Vec3::default()
+        match self {
+            Material::Custom(bsdf) => bsdf.emitted(ray_in, rec),
+            _ => self.emission(rec.u, rec.v, rec.p, rec.front_face),
+        }
+    }
+
+    /// Radiance leaving a point of the surface, seen from its front or back.
+    pub fn emission(&self, u: f32, v: f32, p: Vec3, front_face: bool) -> Color {
+        match self {
+            Material::DiffuseLight { emission, intensity, two_sided } if front_face || *two_sided => {
+                *intensity * emission.value(u, v, p)
+            }
+            Material::Masked { base, .. } => base.emission(u, v, p, front_face),
+            _ => Vec3::default(),
+        }
     }
 
 }
 
+impl Bsdf for Material {
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, mut rng: &mut dyn RngCore) -> Option<(Color, Ray)> {
+        Material::scatter(self, ray_in, rec, &mut rng)
+    }
+
+    fn eval(&self, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Option<Color> {
+        Material::eval(self, ray_in, rec, wi)
+    }
+
+    fn pdf(&self, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Option<f32> {
+        Material::pdf(self, ray_in, rec, wi)
+    }
+
+    fn emitted(&self, ray_in: &Ray, rec: &HitRecord) -> Color {
+        Material::emitted(self, ray_in, rec)
+    }
+}
+
+/// Measured conductors, with `(eta, k)` sampled at roughly 650, 550 and 450nm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conductor {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+    Chrome,
+}
+
+impl Conductor {
+    pub fn ior(self) -> (Color, Color) {
+        match self {
+            Conductor::Gold => (Vec3(0.143, 0.374, 1.442), Vec3(3.983, 2.385, 1.603)),
+            Conductor::Copper => (Vec3(0.200, 0.924, 1.102), Vec3(3.912, 2.452, 2.142)),
+            Conductor::Aluminium => (Vec3(1.657, 0.880, 0.521), Vec3(9.224, 6.270, 4.837)),
+            Conductor::Silver => (Vec3(0.155, 0.117, 0.138), Vec3(4.828, 3.122, 2.147)),
+            Conductor::Chrome => (Vec3(4.370, 2.917, 1.655), Vec3(5.206, 4.231, 3.755)),
+        }
+    }
+}
+
 impl std::fmt::Debug for Material {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.write_str("...")
     }
 }
 
+/// Shading frame whose `u` axis follows the surface's `dpdu`, so anisotropic
+/// highlights stretch along the texture's `u` direction.
+fn tangent_frame(rec: &HitRecord) -> Onb {
+    Onb::from_w_u(rec.normal, rec.dpdu)
+}
+
+/// Reflects or refracts through a perfectly smooth dielectric interface,
+/// choosing between the two by Fresnel reflectance.
+fn smooth_boundary(ir: f32, ray_in: &Ray, rec: &HitRecord, rng: &mut impl Rng) -> Vec3 {
+    let refraction_ratio = if rec.front_face {1.0/ ir} else {ir};
+
+    let unit_direction = Vec3::unit_vector(ray_in.direction);
+    let cos_theta = f32::min((-unit_direction).dot(rec.normal), 1.0);
+    let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+
+    // let direction = reflect(unit_direction, rec.normal);
+
+    // let direction = refract(ray_in.direction, rec.normal, refraction_ratio)
+    // .filter(|_| rng.gen::<f32>() >= reflectance(cos_theta, refraction_ratio))
+    // .unwrap_or_else(|| reflect(ray_in.direction, rec.normal));
+
+    match refraction_ratio * sin_theta > 1.0 || reflectance(cos_theta, refraction_ratio) > rng.gen() {
+        true => reflect(unit_direction, rec.normal),
+        false => refract(unit_direction, rec.normal, refraction_ratio)
+    }
+}
+
+/// BSDF times cosine and sampling density of a rough dielectric interface
+/// for local directions, `eta` being the index beyond the surface over the
+/// index on the side of `wo`. Matches `scatter`, which picks reflection
+/// with probability F and leaves out the radiance scaling by eta² on
+/// refraction, as the smooth dielectric does.
+fn rough_dielectric(ggx: &Ggx, eta: f32, wo: Vec3, wi: Vec3) -> (f32, f32) {
+    if let Some((m, f, pdf)) = ggx.reflection(wo, wi) {
+        let fresnel = fresnel_dielectric(wo.dot(m), eta);
+        return (fresnel * f, fresnel * pdf);
+    }
+    match ggx.transmission(wo, wi, eta) {
+        Some((m, f, pdf)) => {
+            let transmitted = 1.0 - fresnel_dielectric(wo.dot(m), eta);
+            (transmitted * f, transmitted * pdf)
+        }
+        None => (0.0, 0.0),
+    }
+}
+
+/// Scattering events a subsurface walk may take before the light is
+/// considered absorbed.
+const MAX_WALK_STEPS: u32 = 256;
+
+/// Light refracted into a subsurface object, followed from scattering event
+/// to scattering event until it leaves through the boundary.
+struct RandomWalk {
+    ir: f32,
+    albedo: Color,
+    mean_free_path: Color,
+}
+
+impl RandomWalk {
+    /// Throughput of the walk starting along `ray` inside `surface`, and the
+    /// ray it leaves by. `None` if it is still inside after `MAX_WALK_STEPS`.
+    fn exit(&self, surface: &dyn Hittable, ray: Ray, rng: &mut impl Rng) -> Option<(Color, Ray)> {
+        // Flight distances are sampled along a unit direction.
+        let mut ray = Ray::new(ray.origin, Vec3::unit_vector(ray.direction));
+        let sigma_t = self.mean_free_path.map(|mfp| 1.0 / mfp.max(1e-6));
+        let albedo = self.albedo.map(single_scattering_albedo);
+        let mut weight = Vec3::from(1.0);
+        // Only segments starting on the boundary need to skip past it.
+        let mut near = WALK_NEAR;
+
+        for _ in 0..MAX_WALK_STEPS {
+            // Sample the flight distance in one channel at random and weight
+            // by the average pdf over all three.
+            let channel = [sigma_t.x(), sigma_t.y(), sigma_t.z()][rng.gen_range(0..3)];
+            let s = -(1.0 - rng.gen::<f32>()).ln() / channel;
+
+            match surface.hit(&ray, near..s, &mut || rng.gen()) {
+                Some(boundary) => {
+                    let transmittance = beer_lambert(sigma_t, boundary.t);
+                    weight = weight * transmittance / (transmittance.reduce(f32::add) / 3.0);
+
+                    let direction = smooth_boundary(self.ir, &ray, &boundary, rng);
+                    if direction.dot(boundary.normal) < 0.0 {
+                        return Some((weight, Ray::new(boundary.p, direction)));
+                    }
+                    ray = Ray::new(boundary.p, direction);
+                    near = WALK_NEAR;
+                }
+                None => {
+                    let transmittance = beer_lambert(sigma_t, s);
+                    let pdf = (sigma_t * transmittance).reduce(f32::add) / 3.0;
+                    weight = weight * albedo * sigma_t * transmittance / pdf;
+                    ray = Ray::new(ray.at(s), Vec3::random_unit_vector(rng));
+                    near = 0.0;
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Closest distance at which a walk segment can meet the boundary, keeping
+/// it from finding the point it started on.
+const WALK_NEAR: f32 = 0.001;
+
+/// Single-scattering albedo that yields the multiple-scattering `albedo`
+/// seen from outside (Chiang et al. 2016).
+fn single_scattering_albedo(albedo: f32) -> f32 {
+    let a = albedo.clamp(0.0, 0.999);
+    1.0 - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+}
+
 #[inline]
 fn reflectance(cosine:f32, ref_idx:f32) -> f32 {
     let r0 = (1.0-ref_idx) / (1.0+ref_idx);
     let r1 = r0*r0;
     r1 + (1.0 - r1)*(1.0 - cosine).powi(5)
-}
\ No newline at end of file
+}
+
+/// Transmittance after travelling `distance` through a medium with the given
+/// absorption coefficient.
+#[inline]
+pub fn beer_lambert(absorption: Color, distance: f32) -> Color {
+    absorption.map(|sigma| (-sigma * distance).exp())
+}
+
+/// Absorption along the segment that just ended at `rec`; a back-face hit
+/// means that segment ran through the interior.
+fn interior_transmittance(absorption: Color, ray_in: &Ray, rec: &HitRecord) -> Color {
+    if rec.front_face {
+        Vec3(1.0,1.0,1.0)
+    } else {
+        beer_lambert(absorption, rec.t * ray_in.direction.length())
+    }
+}
+
+/// Inverts Beer–Lambert so that white light becomes `color` after `distance`,
+/// which must be positive.
+pub fn absorption_from_color(color: Color, distance: f32) -> Color {
+    assert!(distance > 0.0, "absorption distance must be positive, got {}", distance);
+    color.map(|c| -c.max(1e-6).ln() / distance)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{node_graph::Graph, sphere::Sphere, texture::ImageTexture};
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::f32::consts::PI;
+
+    fn record(material: &Material, front_face: bool) -> HitRecord<'_> {
+        HitRecord {
+            p: Vec3::default(),
+            normal: Vec3(0.0, 0.0, 1.0),
+            geometric_normal: Vec3(0.0, 0.0, 1.0),
+            dpdu: Vec3(1.0, 0.0, 0.0),
+            dpdv: Vec3(0.0, 1.0, 0.0),
+            material,
+            t: 1.0,
+            u: 0.5,
+            v: 0.5,
+            primitive: 0,
+            object: 0,
+            surface: None,
+            front_face,
+        }
+    }
+
+    /// Checks by Monte Carlo that `scatter` samples what `eval` and `pdf`
+    /// describe: the same reflectance weighted by a test function, and a pdf
+    /// that integrates to the chance of scattering and matches the samples.
+    pub(crate) fn check_consistency(material: &Material, front_face: bool) {
+        let rec = record(material, front_face);
+        let ray_in = Ray::new(Vec3(0.4, -0.3, 1.0), Vec3(-0.4, 0.3, -1.0));
+        let g = |w: Vec3| 1.0 + w.x() + w.z() * w.z();
+        let mut rng = StdRng::seed_from_u64(11);
+        let n = 300_000;
+
+        let (mut sampled, mut scattered, mut over_pdf) = (Vec3::default(), 0.0, 0.0);
+        for _ in 0..n {
+            if let Some((weight, ray)) = material.scatter(&ray_in, &rec, &mut rng) {
+                let wi = ray.direction.unit_vector();
+                sampled = sampled + g(wi) * weight;
+                scattered += 1.0;
+                let pdf = material.pdf(&ray_in, &rec, wi).unwrap();
+                assert!(pdf > 0.0, "sampled a direction with zero pdf: {:?}", wi);
+                over_pdf += g(wi) / pdf;
+            }
+        }
+
+        let (mut evaluated, mut pdf_total, mut support) = (Vec3::default(), 0.0, 0.0);
+        for _ in 0..n {
+            let wi = Vec3::random_unit_vector(&mut rng);
+            let f = material.eval(&ray_in, &rec, wi).unwrap();
+            let pdf = material.pdf(&ray_in, &rec, wi).unwrap();
+            evaluated = evaluated + g(wi) * f;
+            pdf_total += pdf;
+            if pdf > 0.0 {
+                support += g(wi);
+            }
+        }
+
+        let sphere = 4.0 * PI / n as f32;
+        let close = |a: f32, b: f32| (a - b).abs() <= 0.04 * a.abs().max(b.abs()) + 0.01;
+        let sampled = sampled / n as f32;
+        let evaluated = sphere * evaluated;
+        for (a, b) in [(sampled.x(), evaluated.x()), (sampled.y(), evaluated.y()), (sampled.z(), evaluated.z())] {
+            assert!(close(a, b), "scatter {:?} against eval {:?}", sampled, evaluated);
+        }
+        assert!(close(scattered / n as f32, sphere * pdf_total), "scattered {} against pdf {}", scattered / n as f32, sphere * pdf_total);
+        assert!(close(over_pdf / n as f32, sphere * support), "samples {} against pdf {}", over_pdf / n as f32, sphere * support);
+    }
+
+    #[test]
+    fn rough_dielectric_eval_matches_scatter() {
+        let glass = Material::rough_dielectric(1.5, 0.6);
+        check_consistency(&glass, true);
+        check_consistency(&glass, false);
+    }
+
+    #[test]
+    fn analytic_variants_match_scatter() {
+        let graph = GraphMaterial::new(Graph::new(), Principled { metallic: 0.5, ..Principled::default() });
+        for material in [
+            Material::Lambertian { albedo: Vec3(0.7, 0.5, 0.3) },
+            Material::conductor(Conductor::Gold, 0.4, 0.5),
+            Material::Cloth { albedo: Vec3(0.5, 0.1, 0.1), sheen: Vec3(0.8, 0.8, 0.8), sheen_roughness: 0.4 },
+            Material::Graph(Arc::new(graph)),
+            Material::masked(Texture::from(1.0), AlphaMode::Cutoff(0.5), Material::metal(Vec3::from(0.8), 0.3)),
+        ] {
+            check_consistency(&material, true);
+        }
+    }
+
+    #[test]
+    fn smooth_interfaces_have_no_eval() {
+        let ray_in = Ray::new(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, -1.0));
+        for material in [
+            Material::Dielectric { ir: 1.5, absorption: Vec3::default() },
+            Material::Subsurface { ir: 1.4, albedo: Vec3::from(0.8), mean_free_path: Vec3::from(0.1) },
+            Material::thin_film(1.3, 400.0, 1.5),
+        ] {
+            let rec = record(&material, true);
+            assert!(material.eval(&ray_in, &rec, Vec3(0.0, 0.0, 1.0)).is_none());
+            assert!(material.pdf(&ray_in, &rec, Vec3(0.0, 0.0, 1.0)).is_none());
+        }
+    }
+
+    #[test]
+    fn thickness_maps_shift_film_colour() {
+        // Left half black, right half white.
+        let map = ImageTexture::new(2, 1, vec![Vec3::from(0.0), Vec3::from(1.0)]);
+        let film = Material::ThinFilm {
+            film_ir: 1.33,
+            substrate_ir: 1.0,
+            thickness: Texture::Image(Arc::new(map)),
+            min_nm: 250.0,
+            max_nm: 450.0,
+        };
+        let ray_in = Ray::new(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, -1.0));
+        let mut rng = StdRng::seed_from_u64(6);
+
+        // Colour of the reflection, normalised to unit brightness.
+        let mut tint = |u: f32| {
+            let rec = HitRecord { u, ..record(&film, true) };
+            (0..10_000)
+                .find_map(|_| {
+                    let (weight, scattered) = film.scatter(&ray_in, &rec, &mut rng)?;
+                    (scattered.direction.z() > 0.0).then(|| weight / weight.reduce(f32::add))
+                })
+                .expect("the film never reflects")
+        };
+        let (thin, thick) = (tint(0.25), tint(0.75));
+        for tint in [thin, thick] {
+            assert!(tint.reduce(f32::max) - tint.reduce(f32::min) > 0.1, "{:?} is nearly grey", tint);
+        }
+        assert!((thin - thick).length() > 0.1, "{:?} against {:?}", thin, thick);
+    }
+
+    #[test]
+    fn subsurface_walk_leaves_through_the_surface() {
+        let skin = Material::Subsurface { ir: 1.4, albedo: Vec3(0.9, 0.6, 0.4), mean_free_path: Vec3(0.02, 0.01, 0.005) };
+        let sphere = Sphere::new(Vec3::default(), 1.0, skin.clone());
+        let ray_in = Ray::new(Vec3(0.0, 0.0, 3.0), Vec3(0.1, 0.0, -1.0));
+        let mut rng = StdRng::seed_from_u64(3);
+        let rec = sphere.hit(&ray_in, 0.001..f32::INFINITY, &mut || rng.gen()).unwrap();
+        let rec = HitRecord { surface: Some(&sphere), ..rec };
+
+        // Far more scattering events than any path depth, all in one call.
+        let n = 2000;
+        let (mut exits, mut total) = (0, Vec3::default());
+        for _ in 0..n {
+            if let Some((weight, scattered)) = skin.scatter(&ray_in, &rec, &mut rng) {
+                assert!((scattered.origin.length() - 1.0).abs() < 1e-3);
+                assert!(scattered.direction.dot(scattered.origin) > 0.0);
+                exits += 1;
+                total = total + weight;
+            }
+        }
+        assert!(exits > n * 4 / 5, "only {} of {} walks got out", exits, n);
+        assert!(total.x() > total.y() && total.y() > total.z());
+    }
+}
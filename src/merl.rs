@@ -0,0 +1,147 @@
+use std::{convert::TryFrom, f32::consts::PI, fs, io, path::Path};
+
+use crate::vec3::{Color, Vec3};
+
+const THETA_H_RES: usize = 90;
+const THETA_D_RES: usize = 90;
+const PHI_D_RES: usize = 180;
+const CHANNEL_LEN: usize = THETA_H_RES * THETA_D_RES * PHI_D_RES;
+
+/// Factors that turn stored MERL values into reflectance, red to blue.
+const CHANNEL_SCALE: [f32; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+/// Isotropic BRDF measured by Matusik et al. (2003), tabulated over
+/// Rusinkiewicz's half/difference angles.
+#[derive(Debug, Clone)]
+pub struct MerlBrdf {
+    /// Red, green and blue tables one after another, already scaled.
+    table: Vec<f32>,
+}
+
+impl MerlBrdf {
+    /// Reads a MERL `.binary` file: three little-endian `i32` dimensions
+    /// followed by `f64` samples for each channel.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        parse(&fs::read(path)?)
+    }
+
+    /// BRDF value for directions in the local shading frame, interpolated
+    /// bilinearly over the two theta axes.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Color {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vec3::default();
+        }
+        let (theta_h, theta_d, phi_d) = half_diff(wo, wi);
+
+        // Theta half is stored on a square-root scale to resolve highlights.
+        let x = (theta_h / (0.5 * PI)).max(0.0).sqrt() * THETA_H_RES as f32;
+        let y = theta_d / (0.5 * PI) * THETA_D_RES as f32;
+        let phi = ((phi_d / PI * PHI_D_RES as f32) as usize).min(PHI_D_RES - 1);
+
+        let (x0, x1, fx) = lerp_indices(x - 0.5, THETA_H_RES);
+        let (y0, y1, fy) = lerp_indices(y - 0.5, THETA_D_RES);
+        let at = |h: usize, d: usize| self.lookup(h, d, phi);
+        let lower = (1.0 - fx) * at(x0, y0) + fx * at(x1, y0);
+        let upper = (1.0 - fx) * at(x0, y1) + fx * at(x1, y1);
+        (1.0 - fy) * lower + fy * upper
+    }
+
+    fn lookup(&self, theta_h: usize, theta_d: usize, phi_d: usize) -> Color {
+        let i = phi_d + PHI_D_RES * (theta_d + THETA_D_RES * theta_h);
+        Vec3(self.table[i], self.table[i + CHANNEL_LEN], self.table[i + 2 * CHANNEL_LEN])
+    }
+}
+
+/// Neighbouring cells and blend factor for a continuous index whose cells
+/// are centred on integers.
+fn lerp_indices(x: f32, res: usize) -> (usize, usize, f32) {
+    let x = x.clamp(0.0, (res - 1) as f32);
+    let i0 = x.floor() as usize;
+    let i1 = (i0 + 1).min(res - 1);
+    (i0, i1, x - i0 as f32)
+}
+
+/// Rusinkiewicz's half/difference angles, with `phi_d` folded into `[0, pi)`
+/// by reciprocity.
+fn half_diff(wo: Vec3, wi: Vec3) -> (f32, f32, f32) {
+    let h = (wo + wi).unit_vector();
+    let theta_h = h.z().clamp(-1.0, 1.0).acos();
+    let phi_h = h.y().atan2(h.x());
+
+    // Rotate `wi` so that the half vector becomes the pole.
+    let rotate_z = |v: Vec3, a: f32| Vec3(v.x() * a.cos() - v.y() * a.sin(), v.x() * a.sin() + v.y() * a.cos(), v.z());
+    let rotate_y = |v: Vec3, a: f32| Vec3(v.x() * a.cos() + v.z() * a.sin(), v.y(), -v.x() * a.sin() + v.z() * a.cos());
+    let d = rotate_y(rotate_z(wi, -phi_h), -theta_h);
+
+    let theta_d = d.z().clamp(-1.0, 1.0).acos();
+    let mut phi_d = d.y().atan2(d.x());
+    if phi_d < 0.0 {
+        phi_d += PI;
+    }
+    (theta_h, theta_d, phi_d)
+}
+
+fn parse(bytes: &[u8]) -> io::Result<MerlBrdf> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    if bytes.len() < 12 {
+        return Err(invalid("truncated MERL header"));
+    }
+    let dims = bytes[..12]
+        .chunks_exact(4)
+        .map(|c| usize::try_from(i32::from_le_bytes([c[0], c[1], c[2], c[3]])).ok().filter(|&d| d > 0))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| invalid("MERL table dimensions must be positive"))?;
+    let len = dims[0].checked_mul(dims[1]).and_then(|n| n.checked_mul(dims[2]));
+    if len != Some(CHANNEL_LEN) {
+        return Err(invalid("unexpected MERL table dimensions"));
+    }
+    if bytes.len() != 12 + 3 * CHANNEL_LEN * 8 {
+        return Err(invalid("MERL table has the wrong length"));
+    }
+
+    let table = bytes[12..]
+        .chunks_exact(8)
+        .enumerate()
+        .map(|(i, c)| {
+            let value = f64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]) as f32;
+            // Unmeasured entries are stored as negative numbers.
+            value.max(0.0) * CHANNEL_SCALE[i / CHANNEL_LEN]
+        })
+        .collect();
+    Ok(MerlBrdf { table })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(dims: [i32; 3], samples: usize, value: f64) -> Vec<u8> {
+        let mut bytes: Vec<u8> = dims.iter().flat_map(|d| d.to_le_bytes()).collect();
+        bytes.extend(std::iter::repeat_n(value.to_le_bytes(), samples).flatten());
+        bytes
+    }
+
+    #[test]
+    fn reads_and_scales_a_constant_table() {
+        let brdf = parse(&file([90, 90, 180], 3 * CHANNEL_LEN, 1500.0)).unwrap();
+        let f = brdf.eval(Vec3(0.3, 0.1, 0.9).unit_vector(), Vec3(-0.5, 0.2, 0.7).unit_vector());
+        assert!((f - Vec3(1.0, 1.15, 1.66)).length() < 1e-4, "{:?}", f);
+        assert!(brdf.eval(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.6, -0.8)).near_zero());
+
+        // Unmeasured entries read as black rather than negative.
+        let brdf = parse(&file([90, 90, 180], 3 * CHANNEL_LEN, -1.0)).unwrap();
+        assert!(brdf.eval(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, 1.0)).near_zero());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(parse(&[0; 8]).is_err());
+        // Negative dimensions whose product wraps around to the table size.
+        assert!(parse(&file([-90, -90, 180], 3 * CHANNEL_LEN, 0.0)).is_err());
+        assert!(parse(&file([0, 90, 180], 0, 0.0)).is_err());
+        assert!(parse(&file([i32::MAX, i32::MAX, i32::MAX], 0, 0.0)).is_err());
+        assert!(parse(&file([90, 90, 90], 3 * 90 * 90 * 90, 0.0)).is_err());
+        assert!(parse(&file([90, 90, 180], 3 * CHANNEL_LEN - 1, 0.0)).is_err());
+    }
+}
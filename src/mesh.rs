@@ -0,0 +1,422 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{aabb::Aabb, bvh::Bvh, hittable::{HitRecord, Hittable}, material::Material, onb::Onb, ray::Ray, texture::Texture, vec3::{Point3, Vec3}};
+
+/// Refinement passes allowed when tessellating for displacement.
+const MAX_REFINE_PASSES: usize = 12;
+
+/// Triangles a displaced mesh may be refined to.
+const MAX_DISPLACED_TRIANGLES: usize = 1 << 22;
+
+/// Indexed triangle mesh with optional per-vertex normals and texture
+/// coordinates, and a BVH built over its triangles.
+#[derive(Debug, Clone)]
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+    triangles: Vec<[usize; 3]>,
+    material: Material,
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    /// Missing normals are computed from the faces, missing `uvs` default to 0.
+    /// Fails if a triangle refers to a vertex that doesn't exist, or if
+    /// `normals` or `uvs` don't give one entry per position.
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Option<Vec<Vec3>>,
+        uvs: Option<Vec<(f32, f32)>>,
+        triangles: Vec<[usize; 3]>,
+        material: Material,
+    ) -> Result<Self, String> {
+        if let Some((index, &t)) = triangles.iter().enumerate().find(|(_, t)| t.iter().any(|&i| i >= positions.len())) {
+            return Err(format!("triangle {} refers to vertex {} of {}", index, t.iter().max().unwrap(), positions.len()));
+        }
+        if let Some(normals) = normals.as_ref().filter(|n| n.len() != positions.len()) {
+            return Err(format!("{} normals for {} vertices", normals.len(), positions.len()));
+        }
+        if let Some(uvs) = uvs.as_ref().filter(|uvs| uvs.len() != positions.len()) {
+            return Err(format!("{} texture coordinates for {} vertices", uvs.len(), positions.len()));
+        }
+
+        let uvs = uvs.unwrap_or_else(|| vec![(0.0, 0.0); positions.len()]);
+        let normals = normals.unwrap_or_else(|| vertex_normals(&positions, &triangles));
+        Ok(TriangleMesh::from_parts(positions, normals, uvs, triangles, material))
+    }
+
+    /// Builds the BVH over triangles already known to be valid.
+    fn from_parts(positions: Vec<Point3>, normals: Vec<Vec3>, uvs: Vec<(f32, f32)>, triangles: Vec<[usize; 3]>, material: Material) -> Self {
+        let bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|t| Aabb::from_points(t.iter().map(|&i| positions[i])))
+            .collect();
+        let bvh = Bvh::build(&bounds);
+
+        TriangleMesh { positions, normals, uvs, triangles, material, bvh }
+    }
+
+    /// A parallelogram spanning `u` and `v` from `corner`, as two triangles
+    /// with texture coordinates running across it.
+    pub fn quad(corner: Point3, u: Vec3, v: Vec3, material: Material) -> Self {
+        let positions = vec![corner, corner + u, corner + u + v, corner + v];
+        let triangles = vec![[0, 1, 2], [0, 2, 3]];
+        let normals = vertex_normals(&positions, &triangles);
+        TriangleMesh::from_parts(positions, normals, vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)], triangles, material)
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = [Point3; 3]> + '_ {
+        self.triangles.iter().map(move |t| t.map(|i| self.positions[i]))
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Corner positions and texture coordinates of one triangle.
+    pub fn triangle(&self, index: usize) -> ([Point3; 3], [(f32, f32); 3]) {
+        let t = self.triangles[index];
+        (t.map(|i| self.positions[i]), t.map(|i| self.uvs[i]))
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    /// True displacement: splits every edge longer than `max_edge` until the
+    /// displaced surface is finely resolved, then moves each vertex along its
+    /// normal by `scale` times the scalar `height`. Edges are split on their
+    /// own rather than per triangle, and vertices sharing a position, such as
+    /// both sides of a UV or normal seam, move together, so no cracks open.
+    /// Refinement stops early rather than grow past
+    /// `MAX_DISPLACED_TRIANGLES`.
+    pub fn displaced(self, height: &Texture, scale: f32, max_edge: f32) -> Self {
+        let TriangleMesh { mut positions, mut normals, mut uvs, mut triangles, material, .. } = self;
+
+        let offset = |p: Point3, n: Vec3, (u, v): (f32, f32)| p + (scale * height.scalar(u, v, p)) * n;
+
+        for _ in 0..MAX_REFINE_PASSES {
+            // A pass at most quadruples the triangles.
+            if triangles.len() > MAX_DISPLACED_TRIANGLES / 4 {
+                break;
+            }
+
+            // Judge edges on the displaced surface, so steep regions refine.
+            let displaced = welded(&positions, &normals, &uvs, offset);
+
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut split = |a: usize, b: usize, face: Vec3, positions: &mut Vec<Point3>, normals: &mut Vec<Vec3>, uvs: &mut Vec<(f32, f32)>| {
+                if (displaced[a] - displaced[b]).length() <= max_edge {
+                    return None;
+                }
+                let key = (a.min(b), a.max(b));
+                Some(*midpoints.entry(key).or_insert_with(|| {
+                    positions.push(0.5 * (positions[a] + positions[b]));
+                    normals.push(midpoint_normal(normals[a], normals[b], face));
+                    uvs.push((0.5 * (uvs[a].0 + uvs[b].0), 0.5 * (uvs[a].1 + uvs[b].1)));
+                    positions.len() - 1
+                }))
+            };
+
+            let mut refined = Vec::with_capacity(triangles.len() * 2);
+            let mut changed = false;
+            for &[a, b, c] in &triangles {
+                let face = face_normal(positions[a], positions[b], positions[c]).unwrap_or(normals[a]);
+                let ab = split(a, b, face, &mut positions, &mut normals, &mut uvs);
+                let bc = split(b, c, face, &mut positions, &mut normals, &mut uvs);
+                let ca = split(c, a, face, &mut positions, &mut normals, &mut uvs);
+                changed |= ab.is_some() || bc.is_some() || ca.is_some();
+
+                // Rotate so that the split edges come first and one pattern
+                // per number of splits covers every case.
+                match (ab, bc, ca) {
+                    (None, None, None) => refined.push([a, b, c]),
+                    (Some(m), None, None) => refined.extend([[a, m, c], [m, b, c]]),
+                    (None, Some(m), None) => refined.extend([[b, m, a], [m, c, a]]),
+                    (None, None, Some(m)) => refined.extend([[c, m, b], [m, a, b]]),
+                    (Some(m1), Some(m2), None) => refined.extend([[m1, b, m2], [a, m1, m2], [a, m2, c]]),
+                    (None, Some(m1), Some(m2)) => refined.extend([[m1, c, m2], [b, m1, m2], [b, m2, a]]),
+                    (Some(m2), None, Some(m1)) => refined.extend([[m1, a, m2], [c, m1, m2], [c, m2, b]]),
+                    (Some(mab), Some(mbc), Some(mca)) => {
+                        refined.extend([[a, mab, mca], [mab, b, mbc], [mca, mbc, c], [mab, mbc, mca]])
+                    }
+                }
+            }
+            triangles = refined;
+            if !changed {
+                break;
+            }
+        }
+
+        let positions = welded(&positions, &normals, &uvs, offset);
+        let normals = vertex_normals(&positions, &triangles);
+        TriangleMesh::from_parts(positions, normals, uvs, triangles, material)
+    }
+
+    fn hit_triangle<'o>(
+        &'o self,
+        index: usize,
+        ray: &Ray,
+        t_range: Range<f32>,
+        rng: &mut dyn FnMut() -> f32,
+    ) -> Option<HitRecord<'o>> {
+        let [i0, i1, i2] = self.triangles[index];
+        let (p0, p1, p2) = (self.positions[i0], self.positions[i1], self.positions[i2]);
+
+        // Möller–Trumbore.
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = ray.direction.cross(e2);
+        let det = e1.dot(pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = ray.origin - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let b2 = ray.direction.dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = e2.dot(qvec) * inv_det;
+        if t < t_range.start || t_range.end < t {
+            return None;
+        }
+        let b0 = 1.0 - b1 - b2;
+
+        let (uv0, uv1, uv2) = (self.uvs[i0], self.uvs[i1], self.uvs[i2]);
+        let u = b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0;
+        let v = b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1;
+        let p = ray.at(t);
+        if self.material.is_cut_out(u, v, p, rng) {
+            return None;
+        }
+
+        let outward = e1.cross(e2).unit_vector();
+        let front_face = ray.direction.dot(outward) < 0.0;
+        let mut shading = (b0 * self.normals[i0] + b1 * self.normals[i1] + b2 * self.normals[i2]).unit_vector();
+        if shading.dot(outward) < 0.0 {
+            shading = -shading;
+        }
+        let sign = if front_face { 1.0 } else { -1.0 };
+
+        let (dpdu, dpdv) = triangle_derivatives([p0, p1, p2], [uv0, uv1, uv2], outward);
+
+        Some(HitRecord {
+            p,
+            normal: sign * shading,
+            geometric_normal: sign * outward,
+            dpdu,
+            dpdv,
+            material: &self.material,
+            t,
+            u,
+            v,
+            primitive: index,
+            object: 0,
+            surface: None,
+            front_face,
+        })
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f32>,
+        rng: &mut dyn FnMut() -> f32,
+    ) -> Option<HitRecord<'o>> {
+        self.bvh.intersect(ray, t_range, |i, range| {
+            self.hit_triangle(i, ray, range, rng).map(|rec| (rec.t, rec))
+        })
+    }
+}
+
+/// `dp/du` and `dp/dv` from the texture coordinates, or an arbitrary frame
+/// around `n` when the mapping is degenerate.
+fn triangle_derivatives(p: [Point3; 3], uv: [(f32, f32); 3], n: Vec3) -> (Vec3, Vec3) {
+    let (du02, dv02) = (uv[0].0 - uv[2].0, uv[0].1 - uv[2].1);
+    let (du12, dv12) = (uv[1].0 - uv[2].0, uv[1].1 - uv[2].1);
+    let dp02 = p[0] - p[2];
+    let dp12 = p[1] - p[2];
+    let det = du02 * dv12 - dv02 * du12;
+    if det.abs() < 1e-12 {
+        let frame = Onb::from_w(n);
+        return (frame.u, frame.v);
+    }
+    let inv = 1.0 / det;
+    ((dv12 * dp02 - dv02 * dp12) * Vec3::from(inv), (du02 * dp12 - du12 * dp02) * Vec3::from(inv))
+}
+
+/// Each vertex moved by `offset`. Vertices at the same position share the
+/// average of their normals and of their offsets, so a seam where the mesh
+/// duplicates vertices moves as one.
+fn welded(
+    positions: &[Point3],
+    normals: &[Vec3],
+    uvs: &[(f32, f32)],
+    offset: impl Fn(Point3, Vec3, (f32, f32)) -> Point3,
+) -> Vec<Point3> {
+    // Adding zero folds -0.0 into 0.0.
+    let key = |p: Point3| [p.x(), p.y(), p.z()].map(|c| (c + 0.0).to_bits());
+    let mut groups: HashMap<[u32; 3], (Vec3, Vec3, usize)> = HashMap::new();
+    for i in 0..positions.len() {
+        let group = groups.entry(key(positions[i])).or_insert((Vec3::default(), Vec3::default(), 0));
+        group.0 = group.0 + normals[i];
+        group.2 += 1;
+    }
+    for i in 0..positions.len() {
+        let group = groups.get_mut(&key(positions[i])).unwrap();
+        let normal = if group.0.near_zero() { normals[i] } else { group.0.unit_vector() };
+        group.1 = group.1 + (offset(positions[i], normal, uvs[i]) - positions[i]);
+    }
+    positions
+        .iter()
+        .map(|&p| {
+            let (_, moved, count) = groups[&key(p)];
+            p + moved / count as f32
+        })
+        .collect()
+}
+
+/// Unit normal of a triangle, `None` if it is degenerate.
+fn face_normal(a: Point3, b: Point3, c: Point3) -> Option<Vec3> {
+    let n = (b - a).cross(c - a);
+    if n.near_zero() { None } else { Some(n.unit_vector()) }
+}
+
+/// Normal halfway between two vertex normals, or `face` where they point in
+/// opposite directions.
+fn midpoint_normal(na: Vec3, nb: Vec3, face: Vec3) -> Vec3 {
+    let n = na + nb;
+    if n.near_zero() { face } else { n.unit_vector() }
+}
+
+/// Area-weighted average of the face normals around each vertex.
+fn vertex_normals(positions: &[Point3], triangles: &[[usize; 3]]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::default(); positions.len()];
+    for &[a, b, c] in triangles {
+        let n = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+        for i in [a, b, c] {
+            normals[i] = normals[i] + n;
+        }
+    }
+    normals
+        .into_iter()
+        .map(|n| if n.near_zero() { Vec3(0.0, 0.0, 1.0) } else { n.unit_vector() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::ImageTexture;
+    use std::sync::Arc;
+
+    fn grey() -> Material {
+        Material::Lambertian { albedo: Vec3::from(0.5) }
+    }
+
+    /// Unit cube with separate vertices, normals and UVs per face, the way
+    /// exporters write hard edges.
+    fn split_cube() -> TriangleMesh {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+        for axis in 0..3 {
+            for side in [0.0, 1.0] {
+                let corner = |a: f32, b: f32| {
+                    let mut c = [0.0; 3];
+                    c[axis] = side;
+                    c[(axis + 1) % 3] = a;
+                    c[(axis + 2) % 3] = b;
+                    Vec3(c[0], c[1], c[2])
+                };
+                let mut n = [0.0; 3];
+                n[axis] = if side == 0.0 { -1.0 } else { 1.0 };
+                let base = positions.len();
+                for (a, b) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                    positions.push(corner(a, b));
+                    normals.push(Vec3(n[0], n[1], n[2]));
+                    uvs.push((a, b));
+                }
+                triangles.extend([[base, base + 1, base + 2], [base, base + 2, base + 3]]);
+            }
+        }
+        TriangleMesh::new(positions, Some(normals), Some(uvs), triangles, grey()).unwrap()
+    }
+
+    fn edge_lengths(mesh: &TriangleMesh) -> impl Iterator<Item = f32> + '_ {
+        mesh.triangles().flat_map(|[a, b, c]| [(a - b).length(), (b - c).length(), (c - a).length()])
+    }
+
+    #[test]
+    fn displacement_splits_long_edges() {
+        let quad = TriangleMesh::quad(Vec3::default(), Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0), grey());
+        let flat = quad.displaced(&Texture::from(0.0), 1.0, 0.3);
+
+        assert!(flat.triangle_count() > 2);
+        assert!(edge_lengths(&flat).all(|l| l <= 0.3));
+        let area: f32 = flat.triangles().map(|[a, b, c]| 0.5 * (b - a).cross(c - a).length()).sum();
+        assert!((area - 1.0).abs() < 1e-4);
+        assert!(flat.triangles().flatten().all(|p| p.z() == 0.0));
+    }
+
+    #[test]
+    fn displaced_seams_stay_closed() {
+        let bumps = ImageTexture::new(2, 2, vec![Vec3::from(0.0), Vec3::from(1.0), Vec3::from(0.5), Vec3::from(0.2)]);
+        let cube = split_cube().displaced(&Texture::Image(Arc::new(bumps)), 0.2, 0.3);
+
+        // On a closed surface without cracks or T-junctions every edge is
+        // shared by exactly two triangles.
+        let key = |p: Point3| [p.x(), p.y(), p.z()].map(|c| (c + 0.0).to_bits());
+        let mut edges: HashMap<_, usize> = HashMap::new();
+        for [a, b, c] in cube.triangles() {
+            for (p, q) in [(a, b), (b, c), (c, a)] {
+                let (p, q) = (key(p), key(q));
+                *edges.entry((p.min(q), p.max(q))).or_default() += 1;
+            }
+        }
+        assert!(cube.triangle_count() > 12);
+        assert!(edges.values().all(|&count| count == 2));
+    }
+
+    #[test]
+    fn malformed_meshes_are_rejected() {
+        let positions = || vec![Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0)];
+        for (mesh, message) in [
+            (TriangleMesh::new(positions(), None, None, vec![[0, 1, 2], [2, 1, 3]], grey()), "triangle 1 refers to vertex 3 of 3"),
+            (TriangleMesh::new(positions(), Some(vec![Vec3(0.0, 0.0, 1.0)]), None, vec![[0, 1, 2]], grey()), "1 normals for 3 vertices"),
+            (TriangleMesh::new(positions(), None, Some(vec![(0.0, 0.0); 4]), vec![[0, 1, 2]], grey()), "4 texture coordinates for 3 vertices"),
+        ] {
+            assert_eq!(mesh.unwrap_err(), message);
+        }
+        assert!(TriangleMesh::new(positions(), None, None, vec![[0, 1, 2]], grey()).is_ok());
+        assert!(TriangleMesh::new(vec![], None, None, vec![], grey()).is_ok());
+    }
+
+    #[test]
+    fn opposite_normals_split_cleanly() {
+        let sheet = TriangleMesh::new(
+            vec![Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0)],
+            Some(vec![Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, -1.0), Vec3(0.0, 0.0, 1.0)]),
+            None,
+            vec![[0, 1, 2]],
+            grey(),
+        )
+        .unwrap();
+        let displaced = sheet.displaced(&Texture::from(1.0), 0.1, 0.5);
+        assert!(displaced.triangles().flatten().all(|p| p.x().is_finite() && p.y().is_finite() && p.z().is_finite()));
+    }
+}
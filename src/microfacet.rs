@@ -0,0 +1,315 @@
+use std::f32::consts::PI;
+
+use crate::vec3::{Color, Vec3};
+
+// All directions here live in the local shading frame, normal along +z.
+
+/// Trowbridge–Reitz (GGX) distribution with independent roughness along the
+/// tangent and bitangent.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        Ggx {
+            alpha_x: alpha_x.max(1e-4),
+            alpha_y: alpha_y.max(1e-4),
+        }
+    }
+
+    /// Maps artist roughness and anisotropy in `[-1, 1]` to alphas.
+    pub fn from_roughness(roughness: f32, anisotropy: f32) -> Self {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(-1.0, 1.0)).sqrt();
+        Ggx::new(alpha / aspect, alpha * aspect)
+    }
+
+    pub fn d(&self, m: Vec3) -> f32 {
+        if m.z() <= 0.0 {
+            return 0.0;
+        }
+        let e = (m.x() / self.alpha_x).powi(2) + (m.y() / self.alpha_y).powi(2) + m.z() * m.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    pub fn lambda(&self, w: Vec3) -> f32 {
+        let z2 = w.z() * w.z();
+        if z2 == 0.0 {
+            return f32::INFINITY;
+        }
+        let a2 = (self.alpha_x * w.x()).powi(2) + (self.alpha_y * w.y()).powi(2);
+        0.5 * (-1.0 + (1.0 + a2 / z2).sqrt())
+    }
+
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated masking-shadowing.
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal visible from `wo` (Heitz 2018).
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        let flip = wo.z() < 0.0;
+        let wo = if flip { -wo } else { wo };
+        let vh = Vec3(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit_vector();
+
+        let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if lensq > 0.0 {
+            Vec3(-vh.y(), vh.x(), 0.0) / lensq.sqrt()
+        } else {
+            Vec3(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        Vec3(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(1e-6)).unit_vector()
+    }
+
+    /// Density of `sample_visible_normal` over microfacet normals. Normals
+    /// facing away from `wo` are never picked.
+    pub fn pdf_visible_normal(&self, wo: Vec3, m: Vec3) -> f32 {
+        if wo.z() == 0.0 {
+            return 0.0;
+        }
+        let wo = if wo.z() < 0.0 { -wo } else { wo };
+        self.g1(wo) * wo.dot(m).max(0.0) * self.d(m) / wo.z()
+    }
+
+    /// Reflection of `wo` into `wi` off the microfacets halfway between
+    /// them. Returns that normal, for the Fresnel term, with the BRDF times
+    /// cosine leaving Fresnel out, `D G2 / (4 cos_o)`, and the density with
+    /// which reflecting `wo` about a visible normal lands on `wi`.
+    pub fn reflection(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, f32, f32)> {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return None;
+        }
+        let m = (wo + wi).unit_vector();
+        let d = self.d(m);
+        Some((m, d * self.g2(wo, wi) / (4.0 * wo.z()), self.g1(wo) * d / (4.0 * wo.z())))
+    }
+
+    /// Refraction of `wo` into `wi` below the surface, `eta` being the index
+    /// below over the index above (Walter et al. 2007). Returns the
+    /// microfacet normal, the BTDF times cosine leaving out Fresnel and the
+    /// eta² scaling of radiance, and the density with which refracting `wo`
+    /// through a visible normal lands on `wi`.
+    pub fn transmission(&self, wo: Vec3, wi: Vec3, eta: f32) -> Option<(Vec3, f32, f32)> {
+        if wo.z() <= 0.0 || wi.z() >= 0.0 {
+            return None;
+        }
+        let m = -(wo + eta * wi);
+        if m.near_zero() {
+            return None;
+        }
+        let m = if m.z() < 0.0 { -m.unit_vector() } else { m.unit_vector() };
+        let (cos_o, cos_i) = (wo.dot(m), wi.dot(m));
+        if cos_o <= 0.0 || cos_i >= 0.0 {
+            return None;
+        }
+
+        // Change of variables from microfacet normals to refracted directions.
+        let denom = cos_o + eta * cos_i;
+        let jacobian = eta * eta * -cos_i / (denom * denom);
+        let d = self.d(m);
+        let f = d * self.g2(wo, wi) * cos_o * jacobian / wo.z();
+        let pdf = self.g1(wo) * d * cos_o * jacobian / wo.z();
+        Some((m, f, pdf))
+    }
+}
+
+/// "Charlie" sheen distribution (Estevez and Kulla 2017): an inverted
+/// Gaussian-like lobe whose fibres catch light at grazing angles.
+pub fn charlie_d(roughness: f32, cos_h: f32) -> f32 {
+    let inv_alpha = 1.0 / (roughness * roughness).max(1e-3);
+    let sin2 = (1.0 - cos_h * cos_h).max(0.0);
+    (2.0 + inv_alpha) * sin2.powf(0.5 * inv_alpha) / (2.0 * PI)
+}
+
+/// Neubelt and Pettineo's visibility term to pair with `charlie_d`, already
+/// divided by the `4 cos_o cos_i` of the microfacet BRDF.
+pub fn sheen_visibility(cos_o: f32, cos_i: f32) -> f32 {
+    1.0 / (4.0 * (cos_i + cos_o - cos_i * cos_o)).max(1e-4)
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with complex index `eta + ik`.
+pub fn fresnel_conductor(cos_i: f32, eta: Color, k: Color) -> Color {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+
+    eta.zip_with(k, |n, k| {
+        let t0 = n * n - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4.0 * n * n * k * k).sqrt();
+        let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+
+        let t1 = a2b2 + cos2;
+        let t2 = 2.0 * a * cos_i;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rs + rp)
+    })
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface, `eta` being the
+/// ratio of the transmitted to the incident index.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(-1.0, 1.0);
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Wavelengths in nanometres that the red, green and blue channels stand for.
+pub const RGB_WAVELENGTHS: [f32; 3] = [650.0, 550.0, 450.0];
+
+/// Reflectance of a thin film of index `n2` and `thickness` nanometres lying
+/// between media `n1` (incident) and `n3`, from the Airy sum over all internal
+/// reflections. Produces the interference colours of soap bubbles and oil.
+pub fn fresnel_thin_film(cos_i: f32, n1: f32, n2: f32, n3: f32, thickness: f32) -> Color {
+    let cos1 = cos_i.clamp(0.0, 1.0);
+    let sin2_1 = 1.0 - cos1 * cos1;
+
+    let sin2_2 = sin2_1 * (n1 / n2).powi(2);
+    let sin2_3 = sin2_1 * (n1 / n3).powi(2);
+    if sin2_2 >= 1.0 || sin2_3 >= 1.0 {
+        return Vec3::from(1.0);
+    }
+    let cos2 = (1.0 - sin2_2).sqrt();
+    let cos3 = (1.0 - sin2_3).sqrt();
+
+    // Amplitude coefficients for s and p polarisation at each interface.
+    let rs12 = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let rp12 = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    let rs23 = (n2 * cos2 - n3 * cos3) / (n2 * cos2 + n3 * cos3);
+    let rp23 = (n3 * cos2 - n2 * cos3) / (n3 * cos2 + n2 * cos3);
+
+    let airy = |r12: f32, r23: f32, cos_delta: f32| {
+        let cross = 2.0 * r12 * r23 * cos_delta;
+        (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+    };
+
+    let phase = 4.0 * PI * n2 * thickness.max(0.0) * cos2;
+    let [r, g, b] = RGB_WAVELENGTHS.map(|lambda| {
+        let cos_delta = (phase / lambda).cos();
+        0.5 * (airy(rs12, rs23, cos_delta) + airy(rp12, rp23, cos_delta))
+    });
+    Vec3(r, g, b)
+}
+
+/// Edge-tint parameterisation of a conductor (Gulbrandsen 2014): returns
+/// `(eta, k)` that reflect `reflectivity` at normal incidence.
+pub fn conductor_from_reflectivity(reflectivity: Color, edge_tint: Color) -> (Color, Color) {
+    let r = reflectivity.map(|r| r.clamp(0.0, 0.99));
+    let n = r.zip_with(edge_tint, |r, g| {
+        let sr = r.sqrt();
+        g * (1.0 - r) / (1.0 + r) + (1.0 - g) * (1.0 + sr) / (1.0 - sr)
+    });
+    let k = r.zip_with(n, |r, n| {
+        (((n + 1.0).powi(2) * r - (n - 1.0).powi(2)) / (1.0 - r)).max(0.0).sqrt()
+    });
+    (n, k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Monte Carlo integral of `f` over the upper hemisphere.
+    fn integrate_hemisphere(samples: usize, mut f: impl FnMut(Vec3) -> f32) -> f32 {
+        let mut rng = StdRng::seed_from_u64(7);
+        let total: f32 = (0..samples)
+            .map(|_| {
+                let z: f32 = rng.gen();
+                let phi = 2.0 * PI * rng.gen::<f32>();
+                let r = (1.0 - z * z).sqrt();
+                f(Vec3(r * phi.cos(), r * phi.sin(), z))
+            })
+            .sum();
+        2.0 * PI * total / samples as f32
+    }
+
+    #[test]
+    fn ggx_distribution_is_normalised() {
+        let ggx = Ggx::new(0.5, 0.3);
+        let projected = integrate_hemisphere(400_000, |m| ggx.d(m) * m.z());
+        assert!((projected - 1.0).abs() < 0.03, "{}", projected);
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_one() {
+        let ggx = Ggx::new(0.6, 0.2);
+        let wo = Vec3(0.5, -0.3, 0.8).unit_vector();
+        let total = integrate_hemisphere(400_000, |m| ggx.pdf_visible_normal(wo, m));
+        assert!((total - 1.0).abs() < 0.03, "{}", total);
+    }
+
+    #[test]
+    fn visible_normal_samples_follow_their_pdf() {
+        let ggx = Ggx::new(0.4, 0.7);
+        let wo = Vec3(-0.6, 0.2, 0.5).unit_vector();
+        let mut rng = StdRng::seed_from_u64(3);
+        let n = 200_000;
+        // Importance-sampled integral of m.z² over the normals visible from
+        // `wo`, against a uniform estimate of the same.
+        let reference = integrate_hemisphere(400_000, |m| if wo.dot(m) > 0.0 { m.z() * m.z() } else { 0.0 });
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let m = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+            assert!(m.z() > 0.0 && wo.dot(m) >= -1e-4);
+            sum += m.z() * m.z() / ggx.pdf_visible_normal(wo, m);
+        }
+        let estimate = sum / n as f32;
+        assert!((estimate - reference).abs() < 0.03 * reference, "{} {}", estimate, reference);
+    }
+
+    #[test]
+    fn dielectric_fresnel_limits() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+        assert!((fresnel_dielectric(0.0, 1.5) - 1.0).abs() < 1e-4);
+        // Leaving glass at a shallow angle is total internal reflection.
+        assert_eq!(fresnel_dielectric(0.3, 1.0 / 1.5), 1.0);
+        assert_eq!(fresnel_dielectric(-0.3, 1.5), 1.0);
+    }
+
+    #[test]
+    fn conductor_without_absorption_is_a_dielectric() {
+        for &cos in &[1.0, 0.8, 0.5, 0.2, 0.05] {
+            let conductor = fresnel_conductor(cos, Vec3::from(1.5), Vec3::default());
+            let dielectric = fresnel_dielectric(cos, 1.5);
+            assert!((conductor.x() - dielectric).abs() < 1e-4, "{} {:?} {}", cos, conductor, dielectric);
+        }
+    }
+
+    #[test]
+    fn edge_tint_reproduces_reflectivity() {
+        let reflectivity = Vec3(0.95, 0.64, 0.54);
+        let (eta, k) = conductor_from_reflectivity(reflectivity, Vec3(0.99, 0.98, 0.98));
+        let head_on = fresnel_conductor(1.0, eta, k);
+        assert!((head_on - reflectivity).length() < 1e-3, "{:?}", head_on);
+    }
+}
@@ -0,0 +1,502 @@
+use std::{collections::HashMap, fs, io, path::Path, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
+
+use rand::Rng;
+
+use crate::{hittable::HitRecord, principled::Principled, ray::Ray, texture::{ImageTexture, Texture}, vec3::{Color, Point3, Vec3}};
+
+/// Source of the ids that tell graphs, and so their nodes, apart.
+static NEXT_GRAPH: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle to a node, only valid for the graph that returned it and its
+/// clones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId {
+    graph: usize,
+    index: usize,
+}
+
+/// One operation in a material graph. Scalars travel as grey colours.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Constant(Color),
+    /// Looks `texture` up at the surface `(u, v)`, or at the first two
+    /// channels of `uv` when given.
+    Texture { texture: Texture, uv: Option<NodeId> },
+    /// Surface coordinates as `(u, v, 0)`.
+    Uv,
+    Position,
+    Add(NodeId, NodeId),
+    Multiply(NodeId, NodeId),
+    /// `a` where `factor` is 0, `b` where it is 1.
+    Mix { a: NodeId, b: NodeId, factor: NodeId },
+    /// Schlick reflectance of an interface of index `ior` towards the viewer.
+    Fresnel { ior: f32 },
+    /// Fractal gradient noise in `[0, 1]` over world position.
+    Noise { scale: f32, octaves: u32 },
+}
+
+/// A DAG of nodes. Inputs must be added before the nodes using them, which
+/// keeps the graph acyclic and lets it evaluate in insertion order.
+#[derive(Debug, Clone)]
+pub struct Graph {
+    id: usize,
+    nodes: Vec<Node>,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Graph { id: NEXT_GRAPH.fetch_add(1, Ordering::Relaxed), nodes: vec![] }
+    }
+}
+
+/// What a graph sees of the point being shaded.
+#[derive(Debug, Clone, Copy)]
+pub struct ShadingContext {
+    pub p: Point3,
+    pub u: f32,
+    pub v: f32,
+    pub normal: Vec3,
+    /// Unit vector towards the viewer.
+    pub view: Vec3,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Graph::default()
+    }
+
+    pub fn add(&mut self, node: Node) -> NodeId {
+        for input in inputs(&node) {
+            assert!(self.contains(input), "node inputs must be added to the same graph first");
+        }
+        self.nodes.push(node);
+        NodeId { graph: self.id, index: self.nodes.len() - 1 }
+    }
+
+    /// Whether `node` was returned by this graph, or the one it was cloned
+    /// from.
+    pub fn contains(&self, node: NodeId) -> bool {
+        node.graph == self.id && node.index < self.nodes.len()
+    }
+
+    pub fn evaluate(&self, ctx: &ShadingContext) -> Vec<Color> {
+        let mut values: Vec<Color> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let value = match node {
+                Node::Constant(c) => *c,
+                Node::Texture { texture, uv } => match uv {
+                    Some(uv) => texture.value(values[uv.index].x(), values[uv.index].y(), ctx.p),
+                    None => texture.value(ctx.u, ctx.v, ctx.p),
+                },
+                Node::Uv => Vec3(ctx.u, ctx.v, 0.0),
+                Node::Position => ctx.p,
+                Node::Add(a, b) => values[a.index] + values[b.index],
+                Node::Multiply(a, b) => values[a.index] * values[b.index],
+                Node::Mix { a, b, factor } => {
+                    let f = values[factor.index];
+                    (Vec3::from(1.0) - f) * values[a.index] + f * values[b.index]
+                }
+                Node::Fresnel { ior } => {
+                    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+                    let cos = ctx.normal.dot(ctx.view).clamp(0.0, 1.0);
+                    Vec3::from(r0 + (1.0 - r0) * (1.0 - cos).powi(5))
+                }
+                Node::Noise { scale, octaves } => Vec3::from(fbm(*scale * ctx.p, *octaves)),
+            };
+            values.push(value);
+        }
+        values
+    }
+}
+
+fn inputs(node: &Node) -> Vec<NodeId> {
+    match node {
+        Node::Texture { uv, .. } => uv.iter().copied().collect(),
+        Node::Add(a, b) | Node::Multiply(a, b) => vec![*a, *b],
+        Node::Mix { a, b, factor } => vec![*a, *b, *factor],
+        _ => vec![],
+    }
+}
+
+/// Parameters of the principled BSDF a graph can drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    BaseColor,
+    Metallic,
+    Roughness,
+    Specular,
+    Sheen,
+    Clearcoat,
+    ClearcoatRoughness,
+    Transmission,
+}
+
+/// A principled BSDF whose inputs come from a node graph; anything not
+/// connected keeps the value in `base`.
+#[derive(Debug, Clone)]
+pub struct GraphMaterial {
+    /// Private, with the connections, so every connected node stays one of
+    /// the graph's own.
+    graph: Graph,
+    pub base: Principled,
+    connections: Vec<(Input, NodeId)>,
+}
+
+impl GraphMaterial {
+    pub fn new(graph: Graph, base: Principled) -> Self {
+        GraphMaterial { graph, base, connections: vec![] }
+    }
+
+    /// Drives `input` from `node`, which must belong to this material's
+    /// graph.
+    pub fn connect(mut self, input: Input, node: NodeId) -> Result<Self, String> {
+        if !self.graph.contains(node) {
+            return Err(format!("node {:?} is not in this material's graph", node));
+        }
+        self.connections.push((input, node));
+        Ok(self)
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    /// The principled parameters at one shading point.
+    pub fn principled(&self, ctx: &ShadingContext) -> Principled {
+        let values = self.graph.evaluate(ctx);
+        let mut p = self.base;
+        for &(input, node) in &self.connections {
+            let value = values[node.index];
+            match input {
+                Input::BaseColor => p.base_color = value,
+                Input::Metallic => p.metallic = value.x(),
+                Input::Roughness => p.roughness = value.x(),
+                Input::Specular => p.specular = value.x(),
+                Input::Sheen => p.sheen = value.x(),
+                Input::Clearcoat => p.clearcoat = value.x(),
+                Input::ClearcoatRoughness => p.clearcoat_roughness = value.x(),
+                Input::Transmission => p.transmission = value.x(),
+            }
+        }
+        p
+    }
+
+    pub fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rng: &mut impl Rng) -> Option<(Color, Ray)> {
+        self.at(ray_in, rec).scatter(ray_in, rec, rng)
+    }
+
+    pub fn eval(&self, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.at(ray_in, rec).eval(ray_in, rec, wi)
+    }
+
+    pub fn pdf(&self, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        self.at(ray_in, rec).pdf(ray_in, rec, wi)
+    }
+
+    /// The principled material at a hit.
+    pub fn at(&self, ray_in: &Ray, rec: &HitRecord) -> Principled {
+        let ctx = ShadingContext {
+            p: rec.p,
+            u: rec.u,
+            v: rec.v,
+            normal: rec.normal,
+            view: -Vec3::unit_vector(ray_in.direction),
+        };
+        self.principled(&ctx)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Arc<Self>> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        GraphMaterial::parse(&source, path.parent().unwrap_or_else(|| Path::new(".")))
+            .map(Arc::new)
+            .map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))
+    }
+
+    /// Reads the text form of a graph, one statement per line:
+    ///
+    /// ```text
+    /// # comments start with a hash
+    /// wood  = texture wood.ppm
+    /// grain = noise 8 4
+    /// dark  = multiply wood 0.6,0.5,0.4
+    /// color = mix wood dark grain
+    /// output base_color color
+    /// output roughness 0.45
+    /// ```
+    ///
+    /// Arguments are node names or literals, a scalar or `r,g,b`. Texture
+    /// paths are relative to `dir`.
+    pub fn parse(source: &str, dir: &Path) -> Result<Self, String> {
+        let mut graph = Graph::new();
+        let mut names: HashMap<String, NodeId> = HashMap::new();
+        let mut connections = vec![];
+
+        for (line_no, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", line_no + 1, msg);
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            if words[0] == "output" {
+                if words.len() != 3 {
+                    return Err(err("expected `output <input> <value>`".into()));
+                }
+                let input = parse_input(words[1]).ok_or_else(|| err(format!("unknown input `{}`", words[1])))?;
+                let node = operand(&mut graph, &names, words[2]).map_err(err)?;
+                connections.push((input, node));
+                continue;
+            }
+
+            if words.len() < 3 || words[1] != "=" {
+                return Err(err("expected `<name> = <op> <args>`".into()));
+            }
+            let (name, op, args) = (words[0], words[2], &words[3..]);
+            let arity = |n: usize| if args.len() == n { Ok(()) } else { Err(err(format!("`{}` takes {} arguments", op, n))) };
+            let number = |s: &str| s.parse::<f32>().map_err(|_| err(format!("expected a number, found `{}`", s)));
+
+            let node = match op {
+                "constant" => {
+                    arity(1)?;
+                    Node::Constant(parse_color(args[0]).ok_or_else(|| err(format!("bad colour `{}`", args[0])))?)
+                }
+                "texture" => {
+                    if args.is_empty() || args.len() > 2 {
+                        return Err(err("`texture` takes a path and optional uv".into()));
+                    }
+                    let image = ImageTexture::load_ppm(dir.join(args[0])).map_err(|e| err(e.to_string()))?;
+                    let uv = match args.get(1) {
+                        Some(a) => Some(operand(&mut graph, &names, a).map_err(err)?),
+                        None => None,
+                    };
+                    Node::Texture { texture: Texture::Image(Arc::new(image)), uv }
+                }
+                "uv" => {
+                    arity(0)?;
+                    Node::Uv
+                }
+                "position" => {
+                    arity(0)?;
+                    Node::Position
+                }
+                "add" | "multiply" => {
+                    arity(2)?;
+                    let a = operand(&mut graph, &names, args[0]).map_err(err)?;
+                    let b = operand(&mut graph, &names, args[1]).map_err(err)?;
+                    if op == "add" { Node::Add(a, b) } else { Node::Multiply(a, b) }
+                }
+                "mix" => {
+                    arity(3)?;
+                    let a = operand(&mut graph, &names, args[0]).map_err(err)?;
+                    let b = operand(&mut graph, &names, args[1]).map_err(err)?;
+                    let factor = operand(&mut graph, &names, args[2]).map_err(err)?;
+                    Node::Mix { a, b, factor }
+                }
+                "fresnel" => {
+                    arity(1)?;
+                    Node::Fresnel { ior: number(args[0])? }
+                }
+                "noise" => {
+                    if args.is_empty() || args.len() > 2 {
+                        return Err(err("`noise` takes a scale and optional octaves".into()));
+                    }
+                    let octaves = args.get(1).map_or(Ok(1.0), |a| number(a))? as u32;
+                    Node::Noise { scale: number(args[0])?, octaves }
+                }
+                _ => return Err(err(format!("unknown node `{}`", op))),
+            };
+            let id = graph.add(node);
+            names.insert(name.to_string(), id);
+        }
+
+        Ok(GraphMaterial { graph, base: Principled::default(), connections })
+    }
+}
+
+/// A named node, or a literal added to the graph on the spot.
+fn operand(graph: &mut Graph, names: &HashMap<String, NodeId>, word: &str) -> Result<NodeId, String> {
+    if let Some(&id) = names.get(word) {
+        return Ok(id);
+    }
+    parse_color(word)
+        .map(|c| graph.add(Node::Constant(c)))
+        .ok_or_else(|| format!("unknown node `{}`", word))
+}
+
+fn parse_color(word: &str) -> Option<Color> {
+    let parts: Vec<f32> = word.split(',').map(|s| s.parse().ok()).collect::<Option<_>>()?;
+    match parts[..] {
+        [v] => Some(Vec3::from(v)),
+        [r, g, b] => Some(Vec3(r, g, b)),
+        _ => None,
+    }
+}
+
+fn parse_input(word: &str) -> Option<Input> {
+    Some(match word {
+        "base_color" => Input::BaseColor,
+        "metallic" => Input::Metallic,
+        "roughness" => Input::Roughness,
+        "specular" => Input::Specular,
+        "sheen" => Input::Sheen,
+        "clearcoat" => Input::Clearcoat,
+        "clearcoat_roughness" => Input::ClearcoatRoughness,
+        "transmission" => Input::Transmission,
+        _ => return None,
+    })
+}
+
+/// Sum of `octaves` layers of gradient noise, remapped to `[0, 1]`.
+fn fbm(p: Point3, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 0.5;
+    let mut p = p;
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * gradient_noise(p);
+        amplitude *= 0.5;
+        p = 2.0 * p;
+    }
+    (0.5 + 0.5 * sum / (1.0 - amplitude * 2.0).max(0.5)).clamp(0.0, 1.0)
+}
+
+/// Perlin-style gradient noise in roughly `[-1, 1]`, with gradients picked
+/// by hashing the lattice point so no tables need to be stored.
+fn gradient_noise(p: Point3) -> f32 {
+    let cell = p.map(f32::floor);
+    let f = p - cell;
+    let fade = f.map(|t| t * t * t * (t * (t * 6.0 - 15.0) + 10.0));
+    let (ix, iy, iz) = (cell.x() as i32, cell.y() as i32, cell.z() as i32);
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let mut h = (ix + dx) as u32;
+        h = h.wrapping_mul(0x8da6_b343) ^ ((iy + dy) as u32).wrapping_mul(0xd816_3841) ^ ((iz + dz) as u32).wrapping_mul(0xcb1a_b31f);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x2c1b_3c6d);
+        h ^= h >> 12;
+        let g = match h % 12 {
+            0 => Vec3(1.0, 1.0, 0.0),
+            1 => Vec3(-1.0, 1.0, 0.0),
+            2 => Vec3(1.0, -1.0, 0.0),
+            3 => Vec3(-1.0, -1.0, 0.0),
+            4 => Vec3(1.0, 0.0, 1.0),
+            5 => Vec3(-1.0, 0.0, 1.0),
+            6 => Vec3(1.0, 0.0, -1.0),
+            7 => Vec3(-1.0, 0.0, -1.0),
+            8 => Vec3(0.0, 1.0, 1.0),
+            9 => Vec3(0.0, -1.0, 1.0),
+            10 => Vec3(0.0, 1.0, -1.0),
+            _ => Vec3(0.0, -1.0, -1.0),
+        };
+        g.dot(f - Vec3(dx as f32, dy as f32, dz as f32))
+    };
+
+    let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fade.x());
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fade.x());
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fade.x());
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fade.x());
+    lerp(lerp(x00, x10, fade.y()), lerp(x01, x11, fade.y()), fade.z())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Straight on at the point (1, 2, 3), with surface coordinates (0.25, 0.75).
+    fn head_on() -> ShadingContext {
+        ShadingContext { p: Vec3(1.0, 2.0, 3.0), u: 0.25, v: 0.75, normal: Vec3(0.0, 0.0, 1.0), view: Vec3(0.0, 0.0, 1.0) }
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{:?} against {:?}", a, b);
+    }
+
+    #[test]
+    fn nodes_evaluate_in_order() {
+        let mut graph = Graph::new();
+        let uv = graph.add(Node::Uv);
+        let position = graph.add(Node::Position);
+        let half = graph.add(Node::Constant(Vec3::from(0.5)));
+        let sum = graph.add(Node::Add(uv, position));
+        let product = graph.add(Node::Multiply(sum, half));
+        let mix = graph.add(Node::Mix { a: uv, b: position, factor: half });
+        let fresnel = graph.add(Node::Fresnel { ior: 1.5 });
+
+        let values = graph.evaluate(&head_on());
+        assert_close(values[uv.index], Vec3(0.25, 0.75, 0.0));
+        assert_close(values[sum.index], Vec3(1.25, 2.75, 3.0));
+        assert_close(values[product.index], Vec3(0.625, 1.375, 1.5));
+        assert_close(values[mix.index], Vec3(0.625, 1.375, 1.5));
+        assert_close(values[fresnel.index], Vec3::from(0.04));
+
+        let grazing = ShadingContext { view: Vec3(1.0, 0.0, 0.0), ..head_on() };
+        assert_close(graph.evaluate(&grazing)[fresnel.index], Vec3::from(1.0));
+    }
+
+    #[test]
+    fn noise_stays_in_range_and_varies() {
+        let mut graph = Graph::new();
+        let noise = graph.add(Node::Noise { scale: 3.0, octaves: 4 });
+        let values: Vec<f32> = (0..200)
+            .map(|i| {
+                let p = Vec3(i as f32 * 0.137, i as f32 * 0.071, 0.5);
+                graph.evaluate(&ShadingContext { p, ..head_on() })[noise.index].x()
+            })
+            .collect();
+        assert!(values.iter().all(|v| (0.0..=1.0).contains(v)));
+        let (min, max) = values.iter().fold((1.0f32, 0.0f32), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+        assert!(max - min > 0.2, "noise spans only {} to {}", min, max);
+    }
+
+    #[test]
+    fn parsed_graphs_drive_principled_inputs() {
+        let source = "
+            # a tinted, half-metal surface
+            tint  = constant 0.8,0.4,0.2
+            dark  = multiply tint 0.5
+            color = mix dark tint 0.5
+            output base_color color
+            output metallic 0.5   # literal
+            output roughness 0.45
+        ";
+        let material = GraphMaterial::parse(source, Path::new(".")).unwrap();
+        let p = material.principled(&head_on());
+        assert_close(p.base_color, Vec3(0.6, 0.3, 0.15));
+        assert_eq!(p.metallic, 0.5);
+        assert_eq!(p.roughness, 0.45);
+        assert_eq!(p.specular, Principled::default().specular);
+    }
+
+    #[test]
+    fn malformed_graphs_are_reported() {
+        for (source, message) in [
+            ("a = constant 1\nb = blur a", "line 2: unknown node `blur`"),
+            ("a = add 1", "line 1: `add` takes 2 arguments"),
+            ("a = constant 1,2", "line 1: bad colour `1,2`"),
+            ("a = multiply b 2", "line 1: unknown node `b`"),
+            ("a constant 1", "line 1: expected `<name> = <op> <args>`"),
+            ("a = fresnel glass", "line 1: expected a number, found `glass`"),
+            ("output shininess 1", "line 1: unknown input `shininess`"),
+            ("output roughness", "line 1: expected `output <input> <value>`"),
+            ("\n\na = texture missing.ppm", "line 3: "),
+        ] {
+            let e = GraphMaterial::parse(source, Path::new("/nonexistent")).unwrap_err();
+            assert!(e.starts_with(message), "{:?}: {}", source, e);
+        }
+    }
+
+    #[test]
+    fn connecting_another_graphs_node_fails() {
+        let mut graph = Graph::new();
+        let own = graph.add(Node::Constant(Vec3::from(0.3)));
+        let mut other = Graph::new();
+        let foreign = other.add(Node::Constant(Vec3::from(0.9)));
+
+        let material = GraphMaterial::new(graph, Principled::default());
+        assert!(material.clone().connect(Input::Roughness, foreign).is_err());
+        let material = material.connect(Input::Roughness, own).unwrap();
+        assert_eq!(material.principled(&head_on()).roughness, 0.3);
+    }
+}
@@ -0,0 +1,173 @@
+use std::ops::Range;
+
+use crate::{hittable::{HitRecord, Hittable}, onb::Onb, ray::Ray, texture::Texture, vec3::Vec3};
+
+/// Step in `u` and `v` for finite differences of a bump map.
+const BUMP_DELTA: f32 = 1e-3;
+
+/// Fine surface detail that tilts the shading normal without moving geometry.
+#[derive(Debug, Clone)]
+pub enum NormalMap {
+    /// Tangent-space normals encoded as `rgb * 2 - 1`, with `strength`
+    /// blending between the true normal at 0 and the map at 1.
+    Tangent { texture: Texture, strength: f32 },
+    /// Scalar height field, `scale` being the height of a texel value of 1.
+    Bump { height: Texture, scale: f32 },
+}
+
+impl NormalMap {
+    /// Replaces the shading normal of `rec`, leaving the geometric one alone.
+    pub fn apply(&self, rec: &mut HitRecord) {
+        // Work with the outward normal, as the maps are authored.
+        let sign = if rec.front_face { 1.0 } else { -1.0 };
+        let n = sign * rec.normal;
+
+        let perturbed = match self {
+            NormalMap::Tangent { texture, strength } => {
+                let frame = Onb::from_w_u(n, rec.dpdu);
+                let m = 2.0 * texture.value(rec.u, rec.v, rec.p) - Vec3::from(1.0);
+                let mapped = Vec3(m.x(), m.y(), m.z().max(1e-3));
+                let local = (1.0 - strength) * Vec3(0.0, 0.0, 1.0) + *strength * mapped;
+                frame.local(local)
+            }
+            NormalMap::Bump { height, scale } => {
+                let h = height.scalar(rec.u, rec.v, rec.p);
+                let dhdu = (height.scalar(rec.u + BUMP_DELTA, rec.v, rec.p) - h) / BUMP_DELTA;
+                let dhdv = (height.scalar(rec.u, rec.v + BUMP_DELTA, rec.p) - h) / BUMP_DELTA;
+
+                let dpdu = rec.dpdu + (scale * dhdu) * n;
+                let dpdv = rec.dpdv + (scale * dhdv) * n;
+                let bumped = dpdu.cross(dpdv);
+                if bumped.near_zero() {
+                    return;
+                }
+                if bumped.dot(n) < 0.0 { -bumped } else { bumped }
+            }
+        };
+
+        if perturbed.near_zero() {
+            return;
+        }
+        rec.normal = sign * perturbed.unit_vector();
+    }
+}
+
+/// Wraps any object so that its hits carry the shading normal from `map`.
+#[derive(Debug)]
+pub struct Mapped<H> {
+    pub object: H,
+    pub map: NormalMap,
+}
+
+impl<H: Hittable> Mapped<H> {
+    pub fn new(object: H, map: NormalMap) -> Self {
+        Mapped { object, map }
+    }
+}
+
+impl<H: Hittable> Hittable for Mapped<H> {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f32>,
+        rng: &mut dyn FnMut() -> f32,
+    ) -> Option<HitRecord<'o>> {
+        let mut rec = self.object.hit(ray, t_range, rng)?;
+        self.map.apply(&mut rec);
+        Some(rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Material, sphere::Sphere, texture::ImageTexture};
+    use std::sync::Arc;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-3, "{:?} against {:?}", a, b);
+    }
+
+    /// A hit on the plane z = 0 seen from above, or from below when not
+    /// `front_face`, with `u` along x and `v` along y.
+    fn record(material: &Material, u: f32, front_face: bool) -> HitRecord<'_> {
+        let normal = if front_face { Vec3(0.0, 0.0, 1.0) } else { Vec3(0.0, 0.0, -1.0) };
+        HitRecord {
+            p: Vec3::default(),
+            normal,
+            geometric_normal: normal,
+            dpdu: Vec3(1.0, 0.0, 0.0),
+            dpdv: Vec3(0.0, 1.0, 0.0),
+            material,
+            t: 1.0,
+            u,
+            v: 0.5,
+            primitive: 0,
+            object: 0,
+            surface: None,
+            front_face,
+        }
+    }
+
+    fn texture(texels: Vec<Vec3>) -> Texture {
+        Texture::Image(Arc::new(ImageTexture::new(texels.len(), 1, texels)))
+    }
+
+    #[test]
+    fn tangent_maps_tilt_along_dpdu() {
+        let material = Material::Lambertian { albedo: Vec3::from(0.5) };
+        // Flat on the left, 30° towards +u on the right.
+        let map = |strength| NormalMap::Tangent {
+            texture: texture(vec![Vec3(0.5, 0.5, 1.0), Vec3(0.75, 0.5, 0.5 + 0.75f32.sqrt() / 2.0)]),
+            strength,
+        };
+        let tilted = Vec3(0.5, 0.0, 0.75f32.sqrt());
+
+        for (u, strength, expected) in [
+            (0.25, 1.0, Vec3(0.0, 0.0, 1.0)),
+            (0.75, 1.0, tilted),
+            (0.75, 0.0, Vec3(0.0, 0.0, 1.0)),
+        ] {
+            for front_face in [true, false] {
+                let mut rec = record(&material, u, front_face);
+                map(strength).apply(&mut rec);
+                let sign = if front_face { 1.0 } else { -1.0 };
+                assert_close(rec.normal, sign * expected);
+                assert_close(rec.geometric_normal, sign * Vec3(0.0, 0.0, 1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn bumps_lean_away_from_rising_ground() {
+        let material = Material::Lambertian { albedo: Vec3::from(0.5) };
+        // A step up at u = 0.5, as steep over one finite-difference step as
+        // the surface is wide.
+        let map = NormalMap::Bump { height: texture(vec![Vec3::from(0.0), Vec3::from(1.0)]), scale: BUMP_DELTA };
+
+        let mut flat = record(&material, 0.25, true);
+        map.apply(&mut flat);
+        assert_close(flat.normal, Vec3(0.0, 0.0, 1.0));
+
+        let mut slope = record(&material, 0.5 - BUMP_DELTA / 2.0, true);
+        map.apply(&mut slope);
+        assert_close(slope.normal, Vec3(-1.0, 0.0, 1.0).unit_vector());
+        assert_close(slope.geometric_normal, Vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn mapped_objects_keep_their_geometry() {
+        let material = Material::Lambertian { albedo: Vec3::from(0.5) };
+        let tilt = NormalMap::Tangent { texture: Vec3(1.0, 0.5, 0.5).into(), strength: 0.5 };
+        let sphere = Mapped::new(Sphere::new(Vec3::default(), 1.0, material), tilt);
+        let ray = Ray::new(Vec3(0.0, 0.0, 3.0), Vec3(0.0, 0.0, -1.0));
+
+        let rec = sphere.hit(&ray, 0.001..f32::INFINITY, &mut || 0.5).unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-4);
+        assert_close(rec.geometric_normal, Vec3(0.0, 0.0, 1.0));
+        // Leaning towards +u only.
+        let bitangent = rec.geometric_normal.cross(rec.dpdu).unit_vector();
+        assert!(rec.normal.dot(rec.dpdu) > 0.1);
+        assert!(rec.normal.dot(bitangent).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,279 @@
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use crate::{Image, camera::Projection, environment::luminance, hittable::HitRecord, material::Material, microfacet::fresnel_conductor, ray::Ray, scene::{LightId, ObjectId, Scene}, texture::Texture, vec3::{Color, Vec3}};
+
+/// Settings for the illustrated look: banded diffuse lighting, ink outlines
+/// and optional hatching.
+#[derive(Debug, Clone)]
+pub struct Toon {
+    /// Unit direction towards the key light, for scenes without lights.
+    pub light_direction: Vec3,
+    pub bands: u32,
+    pub ambient: f32,
+    pub outline_color: Color,
+    /// Relative depth jump between neighbouring pixels that draws a line.
+    pub depth_threshold: f32,
+    /// Creases are drawn where neighbouring normals differ by more than this
+    /// angle, in degrees.
+    pub crease_angle: f32,
+    /// Screen-space hatching pattern: a pixel is inked where the texture
+    /// exceeds its lighting tone, so darker bands get denser strokes.
+    pub hatching: Option<Texture>,
+    /// Pixels per repeat of the hatching texture.
+    pub hatch_scale: f32,
+    pub background: Color,
+}
+
+impl Default for Toon {
+    fn default() -> Self {
+        Toon {
+            light_direction: Vec3(1.0, 1.0, 0.5).unit_vector(),
+            bands: 3,
+            ambient: 0.2,
+            outline_color: Vec3::default(),
+            depth_threshold: 0.05,
+            crease_angle: 40.0,
+            hatching: None,
+            hatch_scale: 16.0,
+            background: Vec3(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// What one primary ray found.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    /// The object hit, `None` for the background and shadow catchers.
+    object: Option<ObjectId>,
+    normal: Vec3,
+    depth: f32,
+    color: Color,
+    /// Banded lighting: of the object hit, or of the shadows falling on a
+    /// catcher. 1 for the plain background.
+    tone: f32,
+}
+
+impl Toon {
+    /// Renders `scene` with one primary ray through each pixel centre, lit by
+    /// its point, spot and directional lights, or by `light_direction` if it
+    /// has none. Depth of field blurs the edge detection, so give the camera
+    /// no aperture. Each row draws a seed from `rng`, so a seeded `rng`
+    /// renders the same image every time.
+    pub fn render(&self, scene: &Scene, camera: &dyn Projection, width: usize, height: usize, rng: &mut impl Rng) -> Image {
+        let seeds: Vec<u64> = (0..height).map(|_| rng.gen()).collect();
+        // Rows bottom to top, like the pixel coordinates passed to the camera.
+        let samples: Vec<Vec<Sample>> = (0..height)
+            .into_par_iter()
+            .map(|y| {
+                let mut rng = StdRng::seed_from_u64(seeds[y]);
+                (0..width)
+                    .map(|x| {
+                        let s = (x as f32 + 0.5) / (width as f32 - 1.0);
+                        let t = (y as f32 + 0.5) / (height as f32 - 1.0);
+                        self.primary(scene, camera, s, t, &mut rng)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let cos_crease = self.crease_angle.to_radians().cos();
+        let is_edge = |a: &Sample, b: &Sample| {
+            if a.object != b.object {
+                return true;
+            }
+            if a.object.is_none() {
+                return false;
+            }
+            (a.depth - b.depth).abs() > self.depth_threshold * a.depth.min(b.depth)
+                || a.normal.dot(b.normal) < cos_crease
+        };
+
+        Image::par_compute(width, height, |x, y| {
+            let here = &samples[y][x];
+            let neighbours = [
+                (x + 1 < width).then(|| &samples[y][x + 1]),
+                (y + 1 < height).then(|| &samples[y + 1][x]),
+                (x > 0).then(|| &samples[y][x - 1]),
+                (y > 0).then(|| &samples[y - 1][x]),
+            ];
+            if neighbours.iter().flatten().any(|n| is_edge(here, n)) {
+                return self.outline_color;
+            }
+            if here.object.is_none() {
+                return here.tone * self.background;
+            }
+
+            let inked = self.hatching.as_ref().is_some_and(|hatching| {
+                let u = x as f32 / self.hatch_scale;
+                let v = y as f32 / self.hatch_scale;
+                hatching.scalar(u, v, Vec3::default()) > here.tone
+            });
+            if inked {
+                self.outline_color
+            } else {
+                here.tone * here.color
+            }
+        })
+    }
+
+    fn primary(&self, scene: &Scene, camera: &dyn Projection, s: f32, t: f32, rng: &mut impl Rng) -> Sample {
+        let background = Sample {
+            object: None,
+            normal: Vec3::default(),
+            depth: f32::INFINITY,
+            color: self.background,
+            tone: 1.0,
+        };
+        let ray = match camera.ray(s, t, rng) {
+            Some(ray) => ray,
+            None => return background,
+        };
+        let rec = match scene.nearest(&ray, true, rng) {
+            Some(rec) => rec,
+            None => return background,
+        };
+
+        let outward = if rec.front_face { rec.normal } else { -rec.normal };
+        if matches!(rec.material, Material::ShadowCatcher) {
+            // Only the shadows show, over the background.
+            let lit = self.lighting(scene, &rec, outward, rng, |_| 1.0);
+            return Sample { tone: self.ambient + (1.0 - self.ambient) * lit, ..background };
+        }
+        let lambert = self.lighting(scene, &rec, outward, rng, |wi| outward.dot(wi).max(0.0));
+        Sample {
+            object: Some(scene.object_at(&rec)),
+            normal: outward,
+            depth: rec.t * ray.direction.length(),
+            color: flat_color(&rec.material.at_hit(&ray, &rec)),
+            tone: self.tone(lambert),
+        }
+    }
+
+    /// Average of `response` towards each light allowed to light `rec` and
+    /// not in shadow, weighted by how bright the light is there. Scenes
+    /// without lights are lit by `light_direction` alone.
+    fn lighting(&self, scene: &Scene, rec: &HitRecord, outward: Vec3, rng: &mut impl Rng, response: impl Fn(Vec3) -> f32) -> f32 {
+        if scene.lights().is_empty() {
+            return response(self.light_direction);
+        }
+        let origin = rec.p + 1e-3 * outward;
+        let (mut lit, mut total) = (0.0, 0.0);
+        for (i, light) in scene.lights().iter().enumerate() {
+            if !scene.illuminates(LightId::Light(i), scene.object_at(rec)) {
+                continue;
+            }
+            let sample = match light.sample(rec.p) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let weight = luminance(sample.li);
+            total += weight;
+            if !scene.occluded(&Ray::new(origin, sample.wi), sample.distance * (1.0 - 1e-4), rng) {
+                lit += weight * response(sample.wi);
+            }
+        }
+        if total > 0.0 { lit / total } else { 0.0 }
+    }
+
+    /// Diffuse lighting quantised to `bands` flat steps above the ambient.
+    fn tone(&self, lambert: f32) -> f32 {
+        let bands = self.bands.max(1) as f32;
+        let banded = ((lambert * bands).ceil() / bands).min(1.0);
+        self.ambient + (1.0 - self.ambient) * banded
+    }
+}
+
+/// A single colour standing in for a material's look.
+fn flat_color(material: &Material) -> Color {
+    match material {
+        Material::Lambertian { albedo } => *albedo,
+        Material::Metal { eta, k, .. } => fresnel_conductor(1.0, *eta, *k),
+        Material::Principled(p) => p.base_color,
+        Material::Coated { base, .. } | Material::Masked { base, .. } => flat_color(base),
+        Material::Subsurface { albedo, .. } | Material::Cloth { albedo, .. } => *albedo,
+        _ => Vec3(1.0, 1.0, 1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{camera::Orthographic, light::Light, mesh::TriangleMesh, scene::LightLink, sphere::Sphere};
+    use std::collections::HashSet;
+
+    fn grey() -> Material {
+        Material::Lambertian { albedo: Vec3::from(0.5) }
+    }
+
+    /// A unit sphere seen head-on, filling half the frame.
+    fn front_view() -> Orthographic {
+        Orthographic::new(Vec3(0.0, 0.0, 5.0), Vec3::default(), Vec3(0.0, 1.0, 0.0), 4.0, 1.0)
+    }
+
+    fn bits(image: &Image) -> Vec<[u32; 3]> {
+        image.rows().iter().flatten().map(|c| [c.x().to_bits(), c.y().to_bits(), c.z().to_bits()]).collect()
+    }
+
+    #[test]
+    fn lighting_falls_into_bands() {
+        let toon = Toon { bands: 3, ambient: 0.25, ..Toon::default() };
+        assert_eq!(toon.tone(0.0), 0.25);
+        assert_eq!(toon.tone(0.1), 0.5);
+        assert_eq!(toon.tone(0.3), 0.5);
+        assert_eq!(toon.tone(0.5), 0.75);
+        assert_eq!(toon.tone(0.9), 1.0);
+        assert_eq!(toon.tone(1.0), 1.0);
+    }
+
+    #[test]
+    fn outlines_trace_the_silhouette() {
+        let toon = Toon::default();
+        let scene = Scene::new(vec![]).with_object(Sphere::new(Vec3::default(), 1.0, grey()));
+        let image = toon.render(&scene, &front_view(), 33, 33, &mut StdRng::seed_from_u64(3));
+        let middle = &image.rows()[16];
+
+        assert_eq!(middle[0].x(), toon.background.x());
+        // Ink separates the sphere from the background on either side.
+        let is_background = |c: &&Vec3| c.x() == toon.background.x();
+        assert!(middle.iter().find(|c| !is_background(c)).unwrap().near_zero(), "{:?}", middle);
+        assert!(middle.iter().rev().find(|c| !is_background(c)).unwrap().near_zero(), "{:?}", middle);
+        assert!(!middle[16].near_zero());
+        // Only the banded tones of the sphere's grey fill the inside.
+        let fills: HashSet<u32> = image.rows().iter().flatten().filter(|c| !c.near_zero() && c.x() < 1.0).map(|c| c.x().to_bits()).collect();
+        assert!(!fills.is_empty() && fills.len() <= toon.bands as usize + 1, "{:?}", fills);
+
+        let again = toon.render(&scene, &front_view(), 33, 33, &mut StdRng::seed_from_u64(3));
+        assert_eq!(bits(&image), bits(&again));
+    }
+
+    #[test]
+    fn scene_lights_and_links_set_the_tone() {
+        let toon = Toon::default();
+        let mut scene = Scene::new(vec![]);
+        let sphere = scene.add_object(Sphere::new(Vec3::default(), 1.0, grey()));
+        let sun = scene.add_light(Light::Directional { direction: Vec3(0.0, 0.0, 1.0), irradiance: Vec3::from(1.0) });
+        let centre = |scene: &Scene| toon.render(scene, &front_view(), 33, 33, &mut StdRng::seed_from_u64(3)).rows()[16][16];
+
+        assert!((centre(&scene).x() - 0.5).abs() < 1e-6);
+        let scene = scene.with_light_link(sun, LightLink::Exclude(HashSet::from([sphere])));
+        assert!((centre(&scene).x() - 0.5 * toon.ambient).abs() < 1e-6);
+    }
+
+    #[test]
+    fn shadow_catchers_show_only_shadows() {
+        let toon = Toon::default();
+        let scene = Scene::new(vec![])
+            .with_object(TriangleMesh::quad(Vec3(-10.0, 0.0, 10.0), Vec3(20.0, 0.0, 0.0), Vec3(0.0, 0.0, -20.0), Material::ShadowCatcher))
+            .with_object(Sphere::new(Vec3(0.0, 1.0, 0.0), 1.0, grey()))
+            .with_light(Light::Directional { direction: Vec3(1.0, 1.0, 0.0), irradiance: Vec3::from(1.0) });
+        let above = Orthographic::new(Vec3(0.0, 5.0, 0.0), Vec3::default(), Vec3(0.0, 0.0, -1.0), 8.0, 1.0);
+        let image = toon.render(&scene, &above, 33, 33, &mut StdRng::seed_from_u64(3));
+
+        for corner in [image.rows()[0][0], image.rows()[0][32], image.rows()[32][0], image.rows()[32][32]] {
+            assert_eq!(corner.x(), toon.background.x());
+        }
+        let shadowed = image.rows().iter().flatten().filter(|c| (c.x() - toon.ambient * toon.background.x()).abs() < 1e-6).count();
+        assert!(shadowed > 10, "{}", shadowed);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::vec3::Vec3;
+
+/// Right-handed orthonormal basis around a surface normal, `w` being the
+/// normal and `u × v = w`.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(n: Vec3) -> Self {
+        let w = n.unit_vector();
+        let a = if w.x().abs() > 0.9 { Vec3(0.0, 1.0, 0.0) } else { Vec3(1.0, 0.0, 0.0) };
+        let v = w.cross(a).unit_vector();
+        let u = v.cross(w);
+        Onb { u, v, w }
+    }
+
+    /// Basis whose `u` axis follows `tangent` projected onto the plane of `n`,
+    /// falling back to an arbitrary frame when the tangent is degenerate.
+    pub fn from_w_u(n: Vec3, tangent: Vec3) -> Self {
+        let w = n.unit_vector();
+        let t = tangent - tangent.dot(w) * w;
+        if t.length_squared() < 1e-12 {
+            return Onb::from_w(n);
+        }
+        let u = t.unit_vector();
+        let v = w.cross(u);
+        Onb { u, v, w }
+    }
+
+    #[inline]
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+
+    #[inline]
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_right_handed() {
+        for &n in &[Vec3(0.0, 0.0, 1.0), Vec3(1.0, 0.0, 0.0), Vec3(-0.3, 0.9, 0.2), Vec3(0.95, -0.1, 0.3)] {
+            for frame in [Onb::from_w(n), Onb::from_w_u(n, Vec3(0.2, 1.0, -0.4))] {
+                assert!((frame.u.cross(frame.v) - frame.w).length() < 1e-5);
+                assert!((frame.w - n.unit_vector()).length() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn tangent_frame_follows_the_tangent() {
+        let frame = Onb::from_w_u(Vec3(0.0, 0.0, 1.0), Vec3(1.0, 1.0, 0.5));
+        assert!((frame.u - Vec3(1.0, 1.0, 0.0).unit_vector()).length() < 1e-5);
+    }
+}
@@ -0,0 +1,256 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::{hittable::HitRecord, microfacet::{Ggx, fresnel_dielectric}, onb::Onb, ray::Ray, vec3::{Color, Vec3, reflect, refract}};
+
+/// Disney-style uber material in the parameters artists and glTF use. Lobes
+/// are layered top to bottom — clearcoat, then metal or dielectric specular,
+/// then transmission or diffuse plus sheen. Each layer passes on the light
+/// its Fresnel reflectance at the macro normal doesn't take, and `scatter`
+/// picks layers with those same probabilities, so `eval` and `pdf` describe
+/// exactly what it samples.
+#[derive(Debug, Clone, Copy)]
+pub struct Principled {
+    pub base_color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Scales dielectric reflectance; 0.5 is an IOR of about 1.5.
+    pub specular: f32,
+    pub anisotropy: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub transmission: f32,
+    pub ior: f32,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            base_color: Vec3(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            anisotropy: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            transmission: 0.0,
+            ior: 1.5,
+        }
+    }
+}
+
+impl Principled {
+    /// The glTF core metallic-roughness model.
+    pub fn metallic_roughness(base_color: Color, metallic: f32, roughness: f32) -> Self {
+        Principled { base_color, metallic, roughness, ..Default::default() }
+    }
+
+    pub fn scatter(&self, ray_in: &Ray, rec: &HitRecord, rng: &mut impl Rng) -> Option<(Color, Ray)> {
+        let uvw = Onb::from_w_u(rec.normal, rec.dpdu);
+        let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let (weight, wi) = self.sample(wo, rec.front_face, rng)?;
+        Some((weight, Ray::new(rec.p, uvw.local(wi))))
+    }
+
+    /// The BSDF times the cosine towards world direction `wi`.
+    pub fn eval(&self, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let uvw = Onb::from_w_u(rec.normal, rec.dpdu);
+        let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+        self.evaluate(wo, uvw.to_local(wi), rec.front_face).0
+    }
+
+    /// Solid-angle density with which `scatter` picks world direction `wi`.
+    pub fn pdf(&self, ray_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        let uvw = Onb::from_w_u(rec.normal, rec.dpdu);
+        let wo = uvw.to_local(-Vec3::unit_vector(ray_in.direction));
+        self.evaluate(wo, uvw.to_local(wi), rec.front_face).1
+    }
+
+    fn sample(&self, wo: Vec3, front_face: bool, rng: &mut impl Rng) -> Option<(Color, Vec3)> {
+        let layers = self.layers(wo, front_face);
+        let mut u: f32 = rng.gen();
+
+        // Each lobe's weight is its BSDF times cosine over the density of
+        // picking the lobe and then the direction, with D cancelling.
+        if u < layers.coat {
+            let coat = Ggx::from_roughness(self.clearcoat_roughness, 0.0);
+            let m = coat.sample_visible_normal(wo, rng.gen(), rng.gen());
+            let wi = reflect(-wo, m);
+            let fresnel = fresnel_dielectric(wo.dot(m), CLEARCOAT_IOR) / fresnel_dielectric(wo.z(), CLEARCOAT_IOR);
+            return specular_reflection(&coat, wo, wi).map(|g| (Vec3::from(fresnel * g), wi));
+        }
+        u -= layers.coat;
+
+        let ggx = Ggx::from_roughness(self.roughness, self.anisotropy);
+        if u < layers.metal {
+            let m = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+            let wi = reflect(-wo, m);
+            return specular_reflection(&ggx, wo, wi).map(|g| (g * self.metal_fresnel(wo.dot(m)), wi));
+        }
+        u -= layers.metal;
+
+        let eta = if front_face { self.ior } else { 1.0 / self.ior };
+        if u < layers.specular {
+            let m = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+            let wi = reflect(-wo, m);
+            let ratio = self.reflectance(wo.dot(m), eta) / self.reflectance(wo.z(), eta);
+            return specular_reflection(&ggx, wo, wi).map(|g| (Vec3::from(ratio * g), wi));
+        }
+        u -= layers.specular;
+
+        if u < layers.transmission {
+            let m = ggx.sample_visible_normal(wo, rng.gen(), rng.gen());
+            if fresnel_dielectric(wo.dot(m), eta) >= 1.0 {
+                return None;
+            }
+            let wi = refract(-wo, m, 1.0 / eta);
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            return Some(((ggx.g2(wo, wi) / ggx.g1(wo)) * self.base_color, wi));
+        }
+
+        if layers.diffuse <= 0.0 {
+            return None;
+        }
+        let mut wi = Vec3(0.0, 0.0, 1.0) + Vec3::random_unit_vector(rng);
+        if wi.near_zero() {
+            wi = Vec3(0.0, 0.0, 1.0);
+        }
+        let wi = wi.unit_vector();
+        Some((self.base_color + self.sheen_color(wo, wi), wi))
+    }
+
+    /// BSDF times cosine and sampling density for local directions.
+    fn evaluate(&self, wo: Vec3, wi: Vec3, front_face: bool) -> (Color, f32) {
+        if wo.z() <= 0.0 {
+            return (Vec3::default(), 0.0);
+        }
+        let layers = self.layers(wo, front_face);
+        let mut f = Vec3::default();
+        let mut pdf = 0.0;
+
+        if layers.coat > 0.0 {
+            let coat = Ggx::from_roughness(self.clearcoat_roughness, 0.0);
+            if let Some((m, lobe, lobe_pdf)) = coat.reflection(wo, wi) {
+                let fresnel = fresnel_dielectric(wo.dot(m), CLEARCOAT_IOR) / fresnel_dielectric(wo.z(), CLEARCOAT_IOR);
+                f = f + Vec3::from(layers.coat * fresnel * lobe);
+                pdf += layers.coat * lobe_pdf;
+            }
+        }
+
+        let ggx = Ggx::from_roughness(self.roughness, self.anisotropy);
+        let eta = if front_face { self.ior } else { 1.0 / self.ior };
+        if let Some((m, lobe, lobe_pdf)) = ggx.reflection(wo, wi) {
+            let dielectric = (1.0 - layers.coat - layers.metal) * self.reflectance(wo.dot(m), eta);
+            f = f + (layers.metal * lobe) * self.metal_fresnel(wo.dot(m)) + Vec3::from(dielectric * lobe);
+            pdf += (layers.metal + layers.specular) * lobe_pdf;
+        }
+        if layers.transmission > 0.0 {
+            if let Some((m, lobe, lobe_pdf)) = ggx.transmission(wo, wi, eta) {
+                if fresnel_dielectric(wo.dot(m), eta) < 1.0 {
+                    f = f + (layers.transmission * lobe) * self.base_color;
+                    pdf += layers.transmission * lobe_pdf;
+                }
+            }
+        }
+        if layers.diffuse > 0.0 && wi.z() > 0.0 {
+            let cosine = wi.z() / PI;
+            f = f + (layers.diffuse * cosine) * (self.base_color + self.sheen_color(wo, wi));
+            pdf += layers.diffuse * cosine;
+        }
+        (f, pdf)
+    }
+
+    /// Probability of each lobe for light arriving along `wo`.
+    fn layers(&self, wo: Vec3, front_face: bool) -> Layers {
+        // Only the outside of the object carries the coat.
+        let coat = if front_face { self.clearcoat.clamp(0.0, 1.0) * fresnel_dielectric(wo.z(), CLEARCOAT_IOR) } else { 0.0 };
+        let metallic = self.metallic.clamp(0.0, 1.0);
+        let eta = if front_face { self.ior } else { 1.0 / self.ior };
+
+        let below_coat = 1.0 - coat;
+        let dielectric = below_coat * (1.0 - metallic);
+        let specular = dielectric * self.reflectance(wo.z(), eta);
+        let transmission = (dielectric - specular) * self.transmission.clamp(0.0, 1.0);
+        let diffuse = if front_face { dielectric - specular - transmission } else { 0.0 };
+        Layers { coat, metal: below_coat * metallic, specular, transmission, diffuse }
+    }
+
+    /// Share of light the dielectric specular lobe reflects at `cos` to its
+    /// microfacet normal.
+    fn reflectance(&self, cos: f32, eta: f32) -> f32 {
+        let fresnel = fresnel_dielectric(cos, eta);
+        if fresnel >= 1.0 { 1.0 } else { (fresnel * 2.0 * self.specular).clamp(0.0, 1.0) }
+    }
+
+    /// Schlick's approximation tinted by the base colour.
+    fn metal_fresnel(&self, cos: f32) -> Color {
+        let f0 = self.base_color;
+        f0 + (1.0 - cos.clamp(0.0, 1.0)).powi(5) * (Vec3::from(1.0) - f0)
+    }
+
+    /// Disney's retro-reflective sheen, added on top of the diffuse albedo.
+    fn sheen_color(&self, wo: Vec3, wi: Vec3) -> Color {
+        if self.sheen <= 0.0 {
+            return Vec3::default();
+        }
+        let h = (wo + wi).unit_vector();
+        let luminance = self.base_color.dot(Vec3(0.2126, 0.7152, 0.0722));
+        let tint = if luminance > 0.0 { self.base_color / luminance } else { Vec3::from(1.0) };
+        let color = (1.0 - self.sheen_tint) * Vec3::from(1.0) + self.sheen_tint * tint;
+        (self.sheen * (1.0 - wi.dot(h).clamp(0.0, 1.0)).powi(5)) * color
+    }
+}
+
+/// Index of refraction of the clearcoat.
+const CLEARCOAT_IOR: f32 = 1.5;
+
+/// Probability of picking each lobe, which is also how much of the light
+/// arriving along one direction it gets. The five add up to one on the front
+/// face; on the back there is no diffuse lobe and the rest is absorbed.
+#[derive(Debug, Clone, Copy)]
+struct Layers {
+    coat: f32,
+    metal: f32,
+    specular: f32,
+    transmission: f32,
+    diffuse: f32,
+}
+
+/// Weight of a visible-normal sampled reflection into `wi`, or `None` if it
+/// ends up below the surface.
+fn specular_reflection(ggx: &Ggx, wo: Vec3, wi: Vec3) -> Option<f32> {
+    if wi.z() <= 0.0 {
+        return None;
+    }
+    Some(ggx.g2(wo, wi) / ggx.g1(wo))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Material, tests::check_consistency};
+
+    #[test]
+    fn eval_matches_scatter() {
+        let plastic = Principled { clearcoat: 0.8, clearcoat_roughness: 0.3, sheen: 0.5, ..Principled::default() };
+        check_consistency(&Material::Principled(plastic), true);
+
+        let brushed = Principled { metallic: 1.0, roughness: 0.6, anisotropy: 0.7, ..Principled::metallic_roughness(Vec3(0.9, 0.6, 0.3), 1.0, 0.6) };
+        check_consistency(&Material::Principled(brushed), true);
+
+        let frosted = Principled { transmission: 1.0, roughness: 0.5, metallic: 0.3, ..Principled::default() };
+        check_consistency(&Material::Principled(frosted), true);
+        check_consistency(&Material::Principled(frosted), false);
+    }
+}
@@ -0,0 +1,155 @@
+/// Piecewise-constant distribution over `[0, 1)` built from bin weights.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    weights: Vec<f32>,
+    cdf: Vec<f32>,
+    total: f32,
+}
+
+impl Distribution1D {
+    pub fn new(weights: Vec<f32>) -> Self {
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        cdf.push(0.0);
+        let mut sum = 0.0;
+        for &w in &weights {
+            sum += w.max(0.0);
+            cdf.push(sum);
+        }
+        // An all-zero distribution degrades to uniform rather than NaNs.
+        if sum > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= sum);
+        } else {
+            let n = weights.len() as f32;
+            cdf.iter_mut().enumerate().for_each(|(i, c)| *c = i as f32 / n);
+        }
+        Distribution1D { weights, cdf, total: sum }
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Sum of the weights.
+    pub fn total(&self) -> f32 {
+        self.total
+    }
+
+    /// Probability of picking bin `i` when sampling discretely.
+    pub fn discrete_pdf(&self, i: usize) -> f32 {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+
+    /// Picks a bin in proportion to its weight, returning it with its
+    /// probability and `u` rescaled to `[0, 1)` within the bin.
+    pub fn sample_discrete(&self, u: f32) -> (usize, f32, f32) {
+        let i = self.cdf.partition_point(|&c| c <= u).clamp(1, self.len()) - 1;
+        let p = self.discrete_pdf(i);
+        let remapped = if p > 0.0 { ((u - self.cdf[i]) / p).clamp(0.0, 0.999_999) } else { 0.5 };
+        (i, p, remapped)
+    }
+
+    /// Samples a continuous position in `[0, 1)`, returned with its density.
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32) {
+        let (i, p, remapped) = self.sample_discrete(u);
+        ((i as f32 + remapped) / self.len() as f32, p * self.len() as f32)
+    }
+
+    /// Density of `sample_continuous` at `x`.
+    pub fn pdf(&self, x: f32) -> f32 {
+        let i = ((x * self.len() as f32) as usize).min(self.len() - 1);
+        self.discrete_pdf(i) * self.len() as f32
+    }
+}
+
+/// Piecewise-constant distribution over the unit square, stored as a
+/// marginal over rows and one conditional per row.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `weights` is row-major with `width` entries per row.
+    pub fn new(weights: &[f32], width: usize) -> Self {
+        let rows: Vec<Distribution1D> = weights.chunks(width).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(rows.iter().map(Distribution1D::total).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    /// Returns `(x, y)` in the unit square with its density.
+    pub fn sample(&self, u1: f32, u2: f32) -> ((f32, f32), f32) {
+        let (y, pdf_y) = self.marginal.sample_continuous(u2);
+        let row = ((y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        let (x, pdf_x) = self.rows[row].sample_continuous(u1);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let row = ((y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_1d_follow_their_pdf() {
+        let distribution = Distribution1D::new(vec![1.0, 0.0, 3.0, 4.0]);
+        let n = 8000;
+        let mut counts = [0; 4];
+        for i in 0..n {
+            let (x, pdf) = distribution.sample_continuous((i as f32 + 0.5) / n as f32);
+            assert!((0.0..1.0).contains(&x));
+            assert!((pdf - distribution.pdf(x)).abs() < 1e-5);
+            counts[(x * 4.0) as usize] += 1;
+        }
+        for (bin, &count) in counts.iter().enumerate() {
+            let expected = distribution.discrete_pdf(bin) * n as f32;
+            assert!((count as f32 - expected).abs() <= 1.0, "bin {}: {} vs {}", bin, count, expected);
+        }
+        assert_eq!(counts[1], 0);
+
+        // The density integrates to one.
+        let integral: f32 = (0..1000).map(|i| distribution.pdf((i as f32 + 0.5) / 1000.0)).sum::<f32>() / 1000.0;
+        assert!((integral - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn all_zero_weights_fall_back_to_uniform() {
+        let distribution = Distribution1D::new(vec![0.0; 5]);
+        assert!((distribution.pdf(0.3) - 1.0).abs() < 1e-6);
+        assert!((distribution.sample_continuous(0.5).1 - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn samples_2d_follow_their_pdf() {
+        let weights = [0.0, 1.0, 2.0, 5.0, 0.5, 0.0, 3.0, 1.0, 1.5];
+        let distribution = Distribution2D::new(&weights, 3);
+        let total: f32 = weights.iter().sum();
+        let n = 120;
+        let mut counts = [0; 9];
+        for i in 0..n {
+            for j in 0..n {
+                let (u1, u2) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                let ((x, y), pdf) = distribution.sample(u1, u2);
+                assert!((pdf - distribution.pdf(x, y)).abs() < 1e-4 * pdf.max(1.0));
+                counts[(y * 3.0) as usize * 3 + (x * 3.0) as usize] += 1;
+            }
+        }
+        for (cell, &count) in counts.iter().enumerate() {
+            let expected = weights[cell] / total * (n * n) as f32;
+            assert!((count as f32 - expected).abs() <= 0.01 * (n * n) as f32, "cell {}: {} vs {}", cell, count, expected);
+            // Each cell is a ninth of the square, so its density is 9 times
+            // its share of the weight.
+            let pdf = distribution.pdf((cell % 3) as f32 / 3.0 + 0.1, (cell / 3) as f32 / 3.0 + 0.1);
+            assert!((pdf - 9.0 * weights[cell] / total).abs() < 1e-4);
+        }
+    }
+}
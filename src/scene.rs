@@ -0,0 +1,467 @@
+use std::{collections::{HashMap, HashSet}, sync::OnceLock};
+
+use rand::Rng;
+
+use crate::{area_light::{AreaLights, Emitter}, environment::{Background, Portal}, hittable::{HitRecord, Hittable}, hittable_list::HittableList, light::Light, light_tree::LightTree, material::Material, ray::Ray, vec3::{Color, Point3, Vec3}};
+
+/// Distance rays travel before they can hit anything, against acne.
+const NEAR: f32 = 0.001;
+
+/// Everything a render needs besides the camera.
+#[derive(Debug, Default)]
+pub struct Scene {
+    /// Only ever appended to, so the handles `add_object` hands out stay
+    /// valid.
+    objects: Vec<Box<dyn Hittable>>,
+    pub background: Background,
+    /// Added through `add_light`, which drops the light tree.
+    lights: Vec<Light>,
+    /// Emissive objects, which are also among `objects`.
+    area_lights: AreaLights,
+    /// Openings an environment map is sampled through, if any.
+    pub portals: Vec<Portal>,
+    /// Objects that cast no shadows.
+    pub shadowless: HashSet<ObjectId>,
+    /// Lights restricted to lighting some objects only. Lights without an
+    /// entry light everything.
+    pub light_links: HashMap<LightId, LightLink>,
+    /// Built on first use, once all the lights are in, with what each light
+    /// in the tree is: indices into `lights` for the point-like ones, then
+    /// every primitive of `area_lights`.
+    light_tree: OnceLock<(LightTree, Vec<usize>)>,
+}
+
+/// Names an object of the scene, as returned by `Scene::add_object` and
+/// `Scene::add_emitter`. It stays valid however many objects follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectId(usize);
+
+/// Names a light for light linking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LightId {
+    /// Index into `Scene::lights()`, as returned by `Scene::add_light`.
+    Light(usize),
+    /// An emissive object added with `Scene::add_emitter`.
+    Emitter(ObjectId),
+}
+
+/// The objects a light may illuminate.
+#[derive(Debug, Clone)]
+pub enum LightLink {
+    Include(HashSet<ObjectId>),
+    Exclude(HashSet<ObjectId>),
+}
+
+/// Light reaching a shading point from one light picked out of the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledLight {
+    pub wi: Vec3,
+    pub radiance: Color,
+    pub distance: f32,
+    /// Density of this sample: the probability of picking the light for
+    /// point-like lights, per solid angle for area lights.
+    pub pdf: f32,
+    /// Whether the light is a point that BSDF sampling can never find.
+    pub delta: bool,
+    pub light: LightId,
+}
+
+impl Scene {
+    /// A scene of `objects`, none of them lights. Add those that need a
+    /// handle, for light linking or to stop their shadows, with
+    /// `add_object` instead.
+    pub fn new(objects: Vec<Box<dyn Hittable>>) -> Self {
+        Scene { objects, ..Scene::default() }
+    }
+
+    pub fn objects(&self) -> &[Box<dyn Hittable>] {
+        &self.objects
+    }
+
+    pub fn with_object(mut self, object: impl Hittable + 'static) -> Self {
+        self.add_object(object);
+        self
+    }
+
+    pub fn add_object(&mut self, object: impl Hittable + 'static) -> ObjectId {
+        self.objects.push(Box::new(object));
+        ObjectId(self.objects.len() - 1)
+    }
+
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.add_light(light);
+        self
+    }
+
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        self.lights.push(light);
+        self.light_tree = OnceLock::new();
+        LightId::Light(self.lights.len() - 1)
+    }
+
+    /// Adds a mesh or sphere to the scene and, if its material gives off
+    /// light, to the lights sampled directly.
+    pub fn with_emitter(mut self, emitter: impl Into<Emitter>) -> Self {
+        self.add_emitter(emitter);
+        self
+    }
+
+    pub fn add_emitter(&mut self, emitter: impl Into<Emitter>) -> ObjectId {
+        let emitter = emitter.into();
+        let id = match emitter.clone() {
+            Emitter::Mesh(mesh) => self.add_object(mesh),
+            Emitter::Sphere(sphere) => self.add_object(sphere),
+        };
+        if matches!(emitter.material(), Material::DiffuseLight { .. }) {
+            self.area_lights.add(emitter, id.0);
+            self.light_tree = OnceLock::new();
+        }
+        id
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn area_lights(&self) -> &AreaLights {
+        &self.area_lights
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Marks the parallelogram spanning `u` and `v` from `corner`, usually a
+    /// window, as the way the environment map gets in. Once there are
+    /// portals the map is only sampled through them, which suits interiors
+    /// where most of it is hidden; light from elsewhere is still found by
+    /// BSDF sampling. The sky ignores portals since its sun is sampled
+    /// directly anyway.
+    pub fn with_portal(mut self, corner: Point3, u: Vec3, v: Vec3) -> Self {
+        let portal = Portal { corner, u, v };
+        assert!(portal.area() > 0.0, "portal spanning {:?} and {:?} has no area", u, v);
+        self.portals.push(portal);
+        self
+    }
+
+    /// Stops `object` from casting shadows. It still shows up in
+    /// reflections, but light behind it is found as if it weren't there.
+    pub fn with_shadowless(mut self, object: ObjectId) -> Self {
+        self.shadowless.insert(object);
+        self
+    }
+
+    pub fn with_light_link(mut self, light: LightId, link: LightLink) -> Self {
+        self.light_links.insert(light, link);
+        self
+    }
+
+    /// Whether `light` is allowed to light `object`.
+    pub fn illuminates(&self, light: LightId, object: ObjectId) -> bool {
+        match self.light_links.get(&light) {
+            None => true,
+            Some(LightLink::Include(objects)) => objects.contains(&object),
+            Some(LightLink::Exclude(objects)) => !objects.contains(&object),
+        }
+    }
+
+    /// The emissive object found at `rec`, if it is a registered light.
+    pub fn light_at(&self, rec: &HitRecord) -> Option<LightId> {
+        self.area_lights.emitter_at(rec).map(|emitter| LightId::Emitter(ObjectId(self.area_lights.object_of(emitter))))
+    }
+
+    /// The object hit at `rec`.
+    pub fn object_at(&self, rec: &HitRecord) -> ObjectId {
+        ObjectId(rec.object)
+    }
+
+    /// Whether the object hit at `rec` casts no shadows.
+    pub fn is_shadowless(&self, rec: &HitRecord) -> bool {
+        self.shadowless.contains(&self.object_at(rec))
+    }
+
+    fn uses_portals(&self) -> bool {
+        !self.portals.is_empty() && matches!(self.background, Background::Environment(_))
+    }
+
+    /// Picks a direction towards the background for next-event estimation
+    /// from `p`, returning it with its radiance and solid-angle density.
+    pub fn sample_background(&self, p: Point3, rng: &mut impl Rng) -> Option<(Vec3, Color, f32)> {
+        if !self.uses_portals() {
+            return self.background.sample(rng.gen(), rng.gen());
+        }
+
+        // Half the samples follow the map's own luminance and are kept only
+        // if they come through a portal, so a sun in the window is found as
+        // readily as without portals. The other half spread uniformly over
+        // the portals' combined area, for the dim sky filling them.
+        if rng.gen::<bool>() {
+            let (wi, radiance, _) = self.background.sample(rng.gen(), rng.gen())?;
+            let pdf = self.background_pdf(&Ray::new(p, wi))?;
+            return self.through_portal(p, wi).then_some((wi, radiance, pdf));
+        }
+        let total: f32 = self.portals.iter().map(Portal::area).sum();
+        let mut pick = rng.gen::<f32>() * total;
+        let portal = self
+            .portals
+            .iter()
+            .find(|portal| {
+                pick -= portal.area();
+                pick < 0.0
+            })
+            .unwrap_or(&self.portals[self.portals.len() - 1]);
+        let target = portal.point_at(rng.gen(), rng.gen());
+        let wi = (target - p).unit_vector();
+
+        let pdf = self.background_pdf(&Ray::new(p, wi))?;
+        Some((wi, self.background.radiance(wi), pdf))
+    }
+
+    /// Density with which `sample_background`, from the origin of `ray`,
+    /// would pick its direction.
+    pub fn background_pdf(&self, ray: &Ray) -> Option<f32> {
+        if !self.uses_portals() {
+            return self.background.pdf(ray.direction);
+        }
+        let area_pdf = 1.0 / self.portals.iter().map(Portal::area).sum::<f32>();
+        let by_area: f32 = self
+            .portals
+            .iter()
+            .filter_map(|portal| portal.pdf(ray.origin, ray.direction, area_pdf))
+            .sum();
+        let by_luminance = match self.through_portal(ray.origin, ray.direction) {
+            true => self.background.pdf(ray.direction)?,
+            false => 0.0,
+        };
+        Some(0.5 * (by_area + by_luminance))
+    }
+
+    /// Whether `direction` from `p` passes through any portal.
+    fn through_portal(&self, p: Point3, direction: Vec3) -> bool {
+        self.portals.iter().any(|portal| portal.pdf(p, direction, 1.0).is_some())
+    }
+
+    fn light_tree(&self) -> &(LightTree, Vec<usize>) {
+        self.light_tree.get_or_init(|| {
+            let point_like: Vec<_> = self
+                .lights
+                .iter()
+                .enumerate()
+                .filter_map(|(i, light)| light.bounds().map(|b| (i, b)))
+                .collect();
+
+            let mut tree_lights: Vec<_> = point_like.iter().map(|&(i, _)| i).collect();
+            tree_lights.extend(0..self.area_lights.len());
+
+            let mut bounds: Vec<_> = point_like.into_iter().map(|(_, b)| b).collect();
+            bounds.extend_from_slice(self.area_lights.light_bounds());
+            (LightTree::build(&bounds), tree_lights)
+        })
+    }
+
+    /// Picks one point or area light in proportion to its estimated
+    /// contribution at `p` and samples it. Directional lights aren't
+    /// included; there are few enough of them to take all of them.
+    pub fn sample_light(&self, p: Point3, rng: &mut impl Rng) -> Option<SampledLight> {
+        let (light_tree, tree_lights) = self.light_tree();
+        let (index, probability) = light_tree.sample(p, rng.gen())?;
+        let point_like = tree_lights.len() - self.area_lights.len();
+
+        if index < point_like {
+            let sample = self.lights[tree_lights[index]].sample(p)?;
+            return Some(SampledLight {
+                wi: sample.wi,
+                radiance: sample.li,
+                distance: sample.distance,
+                pdf: probability,
+                delta: true,
+                light: LightId::Light(tree_lights[index]),
+            });
+        }
+
+        let sample = self.area_lights.sample(index - point_like, p, rng.gen(), rng.gen())?;
+        Some(SampledLight {
+            wi: sample.wi,
+            radiance: sample.radiance,
+            distance: sample.distance,
+            pdf: probability * sample.pdf,
+            delta: false,
+            light: LightId::Emitter(ObjectId(self.area_lights.object_of(self.area_lights.emitter_of(index - point_like)))),
+        })
+    }
+
+    /// Density with which `sample_light`, from the origin of `ray`, would
+    /// have found the emitter it hit at `rec`. `None` if that isn't a light.
+    pub fn light_pdf(&self, ray: &Ray, rec: &HitRecord) -> Option<f32> {
+        let (primitive, pdf) = self.area_lights.pdf(ray, rec)?;
+        let (light_tree, tree_lights) = self.light_tree();
+        let point_like = tree_lights.len() - self.area_lights.len();
+        Some(light_tree.probability(ray.origin, point_like + primitive) * pdf)
+    }
+
+    /// Whether anything that casts shadows blocks `ray` before parameter
+    /// `t_max`.
+    pub fn occluded(&self, ray: &Ray, t_max: f32, rng: &mut impl Rng) -> bool {
+        self.objects.iter().enumerate().any(|(i, obj)| {
+            !self.shadowless.contains(&ObjectId(i))
+                && obj
+                    .hit(ray, NEAR..t_max, &mut || rng.gen())
+                    .is_some_and(|rec| !matches!(rec.material, Material::ShadowCatcher))
+        })
+    }
+
+    /// The nearest hit along `ray`, shadow catchers included only if asked
+    /// for.
+    pub fn nearest<'a>(&'a self, ray: &Ray, catchers: bool, rng: &mut impl Rng) -> Option<HitRecord<'a>> {
+        self.nearest_where(ray, |_, rec| catchers || !matches!(rec.material, Material::ShadowCatcher), rng)
+    }
+
+    /// The nearest hit along `ray` on anything that casts shadows, which is
+    /// what `occluded` would stop at.
+    pub fn nearest_occluder<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<HitRecord<'a>> {
+        self.nearest_where(
+            ray,
+            |object, rec| !self.shadowless.contains(&ObjectId(object)) && !matches!(rec.material, Material::ShadowCatcher),
+            rng,
+        )
+    }
+
+    fn nearest_where<'a>(&'a self, ray: &Ray, keep: impl Fn(usize, &HitRecord) -> bool, rng: &mut impl Rng) -> Option<HitRecord<'a>> {
+        let mut nearest = f32::INFINITY;
+        let mut hit_record = None;
+
+        for (object, obj) in self.objects.iter().enumerate() {
+            if let Some(rec) = obj.hit(ray, NEAR..nearest, &mut || rng.gen()) {
+                if keep(object, &rec) {
+                    nearest = rec.t;
+                    hit_record = Some(HitRecord { object, surface: Some(&**obj), ..rec });
+                }
+            }
+        }
+
+        hit_record
+    }
+}
+
+impl HittableList for Scene {
+    fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<HitRecord<'a>> {
+        self.nearest(ray, false, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{environment::EnvironmentMap, mesh::TriangleMesh, sphere::Sphere, texture::ImageTexture};
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::{f32::consts::PI, sync::Arc};
+
+    #[test]
+    fn lights_added_either_way_are_sampled_consistently() {
+        let lamp = |intensity: f32| Material::diffuse_light(Vec3::from(1.0), intensity);
+        let mut scene = Scene::new(vec![])
+            .with_light(Light::Point { position: Vec3(0.0, 4.0, 0.0), intensity: Vec3::from(5.0), profile: None })
+            .with_emitter(Sphere::new(Vec3(3.0, 1.0, 0.0), 0.5, lamp(4.0)));
+        scene.add_emitter(TriangleMesh::quad(Vec3(-2.0, 3.0, -1.0), Vec3(1.0, 0.0, 0.0), Vec3(0.0, 0.0, 1.0), lamp(8.0)));
+        scene.add_light(Light::spot(Vec3(0.0, 4.0, 1.0), Vec3::default(), Vec3::from(5.0), 30.0, 40.0));
+        scene.add_emitter(Sphere::new(Vec3(-3.0, 1.0, 0.0), 0.3, Material::Lambertian { albedo: Vec3::from(0.5) }));
+        assert_eq!(scene.lights().len(), 2);
+        assert_eq!(scene.area_lights().emitters().len(), 2);
+
+        let p = Vec3::default();
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut seen = HashSet::new();
+        for _ in 0..4000 {
+            let sample = scene.sample_light(p, &mut rng).unwrap();
+            seen.insert(sample.light);
+            if sample.delta {
+                continue;
+            }
+            // A BSDF sample in the same direction must find the same light
+            // with the same density.
+            let ray = Ray::new(p, sample.wi);
+            let rec = scene.hit_top(&ray, &mut rng).unwrap();
+            assert_eq!(scene.light_at(&rec), Some(sample.light));
+            let pdf = scene.light_pdf(&ray, &rec).unwrap();
+            assert!((pdf - sample.pdf).abs() < 1e-3 * sample.pdf, "{} vs {}", pdf, sample.pdf);
+        }
+        assert_eq!(seen.len(), 4);
+    }
+
+    #[test]
+    fn lights_added_after_sampling_are_sampled() {
+        let mut scene = Scene::new(vec![]).with_light(Light::Point { position: Vec3(0.0, 4.0, 0.0), intensity: Vec3::from(5.0), profile: None });
+        let mut rng = StdRng::seed_from_u64(2);
+        assert_eq!(scene.sample_light(Vec3::default(), &mut rng).unwrap().light, LightId::Light(0));
+
+        let lamp = scene.add_emitter(Sphere::new(Vec3(0.0, -4.0, 0.0), 0.5, Material::diffuse_light(Vec3::from(1.0), 4.0)));
+        let spot = scene.add_light(Light::spot(Vec3(4.0, 0.0, 0.0), Vec3::default(), Vec3::from(5.0), 30.0, 40.0));
+        let seen: HashSet<_> = (0..1000).filter_map(|_| scene.sample_light(Vec3::default(), &mut rng)).map(|sample| sample.light).collect();
+        assert_eq!(seen, HashSet::from([LightId::Light(0), LightId::Emitter(lamp), spot]));
+    }
+
+    #[test]
+    fn lights_are_recognised_by_object_not_material() {
+        let lamp = Material::diffuse_light(Vec3::from(1.0), 4.0);
+        let mut scene = Scene::new(vec![]);
+        let left = scene.add_emitter(Sphere::new(Vec3(-2.0, 0.0, 0.0), 0.5, lamp.clone()));
+        let right = scene.add_emitter(Sphere::new(Vec3(2.0, 0.0, 0.0), 0.5, lamp));
+        let mut rng = StdRng::seed_from_u64(4);
+
+        for (x, id) in [(-2.0, left), (2.0, right)] {
+            let ray = Ray::new(Vec3(x, 0.0, 5.0), Vec3(0.0, 0.0, -1.0));
+            let rec = scene.hit_top(&ray, &mut rng).unwrap();
+            // Shading swaps in a resolved copy of the material.
+            let resolved = rec.material.clone();
+            let rec = HitRecord { material: &resolved, ..rec };
+            assert_eq!(scene.light_at(&rec), Some(LightId::Emitter(id)));
+            assert!(scene.light_pdf(&ray, &rec).is_some());
+        }
+    }
+
+    /// A dim environment with a sun in one texel, seen through a window.
+    fn window_scene() -> Scene {
+        let mut texels = vec![Vec3::from(1.0); 32];
+        texels[8 + 4] = Vec3::from(1000.0);
+        let map = EnvironmentMap::new(ImageTexture::new(8, 4, texels), 0.0, 1.0);
+        Scene::new(vec![])
+            .with_background(Background::Environment(Arc::new(map)))
+            .with_portal(Vec3(-1.0, -0.5, -2.0), Vec3(3.0, 0.0, 0.0), Vec3(0.0, 2.5, 0.0))
+    }
+
+    #[test]
+    fn portal_sampling_is_unbiased() {
+        let scene = window_scene();
+        let p = Vec3::default();
+        let mut rng = StdRng::seed_from_u64(21);
+        let n = 200_000;
+
+        let mut estimate = 0.0;
+        for _ in 0..n {
+            if let Some((wi, radiance, pdf)) = scene.sample_background(p, &mut rng) {
+                let expected = scene.background_pdf(&Ray::new(p, wi)).unwrap();
+                assert!((pdf - expected).abs() <= 1e-4 * expected);
+                estimate += radiance.x() / pdf;
+            }
+        }
+        estimate /= n as f32;
+
+        // Light through the window, by sampling the whole sphere uniformly.
+        let mut reference = 0.0;
+        for _ in 0..n {
+            let wi = Vec3::random_unit_vector(&mut rng);
+            if scene.through_portal(p, wi) {
+                reference += scene.background.radiance(wi).x();
+            }
+        }
+        reference *= 4.0 * PI / n as f32;
+        assert!((estimate - reference).abs() < 0.03 * reference, "{} vs {}", estimate, reference);
+    }
+
+    #[test]
+    #[should_panic(expected = "no area")]
+    fn degenerate_portals_are_rejected() {
+        Scene::new(vec![]).with_portal(Vec3::default(), Vec3(1.0, 0.0, 0.0), Vec3(2.0, 0.0, 0.0));
+    }
+}
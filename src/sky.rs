@@ -0,0 +1,229 @@
+use std::f32::consts::PI;
+
+use crate::{onb::Onb, vec3::{Color, Vec3}};
+
+/// Angular radius of the sun seen from the ground, in radians.
+const SUN_ANGULAR_RADIUS: f32 = 0.004_65;
+
+/// Converts the model's kcd/m² to the scene's radiance units: one unit is
+/// 20 kcd/m², so the ~10 kcd/m² zenith of a clear day with the sun 60° up
+/// renders at 0.5 with no exposure adjustment. Rescale with
+/// `Sky::with_intensity` rather than here.
+const SKY_SCALE: f32 = 0.05;
+
+/// Sun irradiance above the atmosphere, in the units `SKY_SCALE` implies
+/// (one unit is 20 klux). The true ~128 klux would be 6.4; half that keeps a
+/// white diffuse surface under a high sun and a clear sky reflecting about
+/// 1.0, where it would otherwise clip.
+const SUN_IRRADIANCE: f32 = 3.0;
+
+/// Preetham et al. (1999) clear-sky daylight with a sun disk. The sky is
+/// analytic; the sun is small and bright enough that it needs sampling
+/// explicitly, which `Background` does through `sample_sun`.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    /// Unit vector towards the centre of the sun.
+    sun_direction: Vec3,
+    turbidity: f32,
+    intensity: f32,
+    sun_intensity: f32,
+    /// Colour the sky is multiplied by when reflected as ground below the
+    /// horizon.
+    pub ground_albedo: Color,
+    zenith: Vec3,
+    perez: [[f32; 5]; 3],
+    sun_radiance: Color,
+}
+
+impl Sky {
+    /// `elevation` is the sun's angle above the horizon and `azimuth` its
+    /// bearing clockwise from `-z` towards `+x`, both in degrees. Turbidity
+    /// runs from about 2 for a crisp clear day to 10 for haze.
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        let (el, az) = (elevation.to_radians(), azimuth.to_radians());
+        let sun_direction = Vec3(el.cos() * az.sin(), el.sin(), -el.cos() * az.cos());
+        let t = turbidity.clamp(1.7, 10.0);
+        // The model breaks down once the sun has set.
+        let theta_s = (0.5 * PI - el).clamp(0.0, 0.5 * PI - 1e-3);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (s1, s2, s3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+        let zenith_yc = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+
+        // Perez values are used relative to the zenith.
+        let zenith = Vec3(
+            zenith_y.max(0.0) / perez_f(perez[0], 0.0, theta_s),
+            zenith_x / perez_f(perez[1], 0.0, theta_s),
+            zenith_yc / perez_f(perez[2], 0.0, theta_s),
+        );
+
+        let mut sky = Sky {
+            sun_direction,
+            turbidity: t,
+            intensity: 1.0,
+            sun_intensity: 1.0,
+            ground_albedo: Vec3::from(0.3),
+            zenith,
+            perez,
+            sun_radiance: Vec3::default(),
+        };
+        sky.sun_radiance = sky.compute_sun_radiance();
+        sky
+    }
+
+    /// Scales the sky and the sun, 1.0 for both being the default.
+    pub fn with_intensity(mut self, sky: f32, sun: f32) -> Self {
+        self.intensity = sky;
+        self.sun_intensity = sun;
+        self.sun_radiance = self.compute_sun_radiance();
+        self
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let d = direction.unit_vector();
+        if d.y() < 0.0 {
+            // Stand-in ground: the sky as a diffuse floor would see it.
+            return self.ground_albedo * self.sky_radiance(Vec3(d.x(), -d.y(), d.z()));
+        }
+        let mut radiance = self.sky_radiance(d);
+        if d.dot(self.sun_direction) >= SUN_ANGULAR_RADIUS.cos() {
+            radiance = radiance + self.sun_radiance;
+        }
+        radiance
+    }
+
+    /// Uniform sample of the sun disk: direction, radiance and solid-angle
+    /// density.
+    pub fn sample_sun(&self, u1: f32, u2: f32) -> Option<(Vec3, Color, f32)> {
+        if self.sun_direction.y() <= 0.0 {
+            return None;
+        }
+        let cos_max = SUN_ANGULAR_RADIUS.cos();
+        let cos_theta = 1.0 - u1 * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let local = Vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let direction = Onb::from_w(self.sun_direction).local(local);
+        Some((direction, self.radiance(direction), 1.0 / sun_solid_angle()))
+    }
+
+    /// Density of `sample_sun` towards `direction`.
+    pub fn sun_pdf(&self, direction: Vec3) -> f32 {
+        let inside = direction.unit_vector().dot(self.sun_direction) >= SUN_ANGULAR_RADIUS.cos();
+        if inside && self.sun_direction.y() > 0.0 { 1.0 / sun_solid_angle() } else { 0.0 }
+    }
+
+    fn sky_radiance(&self, d: Vec3) -> Color {
+        let theta = d.y().clamp(0.0, 1.0).acos().min(0.5 * PI - 1e-3);
+        let gamma = d.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let big_y = self.zenith.x() * perez_f(self.perez[0], theta, gamma);
+        let x = self.zenith.y() * perez_f(self.perez[1], theta, gamma);
+        let y = self.zenith.z() * perez_f(self.perez[2], theta, gamma);
+        (self.intensity * SKY_SCALE) * xyy_to_rgb(x, y, big_y)
+    }
+
+    /// Sunlight after Rayleigh and aerosol extinction along the slant path,
+    /// spread evenly over the disk.
+    fn compute_sun_radiance(&self) -> Color {
+        let theta_s = (0.5 * PI - self.sun_direction.y().clamp(-1.0, 1.0).asin()).clamp(0.0, 0.5 * PI - 1e-3);
+        let zenith_deg = theta_s.to_degrees();
+        let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - zenith_deg).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+
+        let [r, g, b] = [0.65f32, 0.55, 0.45].map(|lambda| {
+            let rayleigh = 0.008_735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        });
+        (self.sun_intensity * SUN_IRRADIANCE / sun_solid_angle()) * Vec3(r, g, b)
+    }
+}
+
+fn sun_solid_angle() -> f32 {
+    2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos())
+}
+
+/// Perez et al.'s sky luminance distribution.
+fn perez_f(c: [f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, cc, d, e] = c;
+    (1.0 + a * (b / theta.cos().max(1e-3)).exp()) * (1.0 + cc * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+/// CIE xyY to linear sRGB.
+fn xyy_to_rgb(x: f32, y: f32, big_y: f32) -> Color {
+    if y <= 0.0 {
+        return Vec3::default();
+    }
+    let big_x = x / y * big_y;
+    let big_z = (1.0 - x - y) / y * big_y;
+    Vec3(
+        3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z,
+    )
+    .map(|c| c.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::luminance;
+
+    const UP: Vec3 = Vec3(0.0, 1.0, 0.0);
+
+    #[test]
+    fn sun_direction_follows_elevation_and_azimuth() {
+        let close = |a: Vec3, b: Vec3| (a - b).length() < 1e-5;
+        assert!(close(Sky::new(90.0, 0.0, 3.0).sun_direction(), UP));
+        assert!(close(Sky::new(0.0, 0.0, 3.0).sun_direction(), Vec3(0.0, 0.0, -1.0)));
+        assert!(close(Sky::new(0.0, 90.0, 3.0).sun_direction(), Vec3(1.0, 0.0, 0.0)));
+        let half = 0.5f32.sqrt();
+        assert!(close(Sky::new(45.0, 180.0, 3.0).sun_direction(), Vec3(0.0, half, half)));
+
+        let sky = Sky::new(30.0, 200.0, 3.0);
+        let (direction, radiance, pdf) = sky.sample_sun(0.3, 0.7).unwrap();
+        assert!(direction.dot(sky.sun_direction()) >= SUN_ANGULAR_RADIUS.cos() - 1e-6);
+        assert!(luminance(radiance) > luminance(sky.radiance(UP)));
+        assert_eq!(pdf, sky.sun_pdf(direction));
+        assert!(Sky::new(-5.0, 0.0, 3.0).sample_sun(0.3, 0.7).is_none());
+    }
+
+    #[test]
+    fn zenith_luminance_follows_the_model() {
+        let zenith = |elevation: f32, turbidity: f32| luminance(Sky::new(elevation, 0.0, turbidity).radiance(UP));
+
+        // Preetham's zenith luminance for turbidity 3 and the sun 60° up is
+        // 10.4 kcd/m².
+        assert!((zenith(60.0, 3.0) / SKY_SCALE - 10.4).abs() < 0.5, "{}", zenith(60.0, 3.0));
+        // Brighter the higher the sun is and the hazier the air.
+        assert!(zenith(10.0, 3.0) < zenith(30.0, 3.0));
+        assert!(zenith(30.0, 3.0) < zenith(60.0, 3.0));
+        assert!(zenith(60.0, 2.0) < zenith(60.0, 3.0));
+        assert!(zenith(60.0, 3.0) < zenith(60.0, 6.0));
+    }
+
+    #[test]
+    fn low_and_hazy_suns_are_dimmer_and_redder() {
+        let sun = |elevation: f32, turbidity: f32| Sky::new(elevation, 0.0, turbidity).sun_radiance;
+        let redness = |c: Color| c.x() / c.z();
+        let (high, low, hazy) = (sun(60.0, 3.0), sun(10.0, 3.0), sun(60.0, 8.0));
+        assert!(luminance(low) < luminance(high) && redness(low) > redness(high));
+        assert!(luminance(hazy) < luminance(high) && redness(hazy) > redness(high));
+    }
+}
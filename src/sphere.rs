@@ -17,6 +17,18 @@ impl Sphere {
             material
         }
     }
+
+    pub fn center(&self) -> Point3 {
+        self.center
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
 }
 
 impl Hittable for Sphere {
@@ -24,8 +36,8 @@ impl Hittable for Sphere {
         &'o self,
         r: &Ray,
         t_range: Range<f32>,
-        _rng: &mut dyn FnMut() -> f32,
-    ) -> Option<HitRecord> {
+        rng: &mut dyn FnMut() -> f32,
+    ) -> Option<HitRecord<'o>> {
         let oc:Vec3 = r.origin - self.center;
         let a = r.direction.length_squared();
         let half_b = oc.dot(r.direction);
@@ -35,27 +47,62 @@ impl Hittable for Sphere {
 
         if discriminant < 0.0 {return None;};
         let sqrtd = discriminant.sqrt();
-        let mut root = (-half_b - sqrtd)/a;
-        if root < t_range.start || t_range.end < root {
-            root = (-half_b + sqrtd) / a;
+
+        for root in [(-half_b - sqrtd)/a, (-half_b + sqrtd)/a] {
             if root < t_range.start || t_range.end < root {
-                return None;
+                continue;
             }
-        }
 
-        let outward_normal:Vec3 = (r.at(root) - self.center) / self.radius;
-        let f_face = r.direction.dot(outward_normal) < 0.0;
-        // let material = self.material;
+            let outward_normal:Vec3 = (r.at(root) - self.center) / self.radius;
+            let (u, v) = sphere_uv(outward_normal);
+            if self.material.is_cut_out(u, v, r.at(root), rng) {
+                continue;
+            }
 
-        return Some(HitRecord {
-            t: root,
-            p: r.at(root),
-            normal: match f_face {
+            let f_face = r.direction.dot(outward_normal) < 0.0;
+            let (dpdu, dpdv) = sphere_derivatives(r.at(root) - self.center);
+            let normal = match f_face {
                 true => outward_normal,
                 false => -outward_normal
-            },
-            material: &self.material,
-            front_face: f_face
-        });
+            };
+            // let material = self.material;
+
+            return Some(HitRecord {
+                t: root,
+                p: r.at(root),
+                normal,
+                geometric_normal: normal,
+                dpdu,
+                dpdv,
+                material: &self.material,
+                u,
+                v,
+                primitive: 0,
+                object: 0,
+                surface: None,
+                front_face: f_face
+            });
+        }
+
+        None
     }
-}
\ No newline at end of file
+}
+
+/// Longitude/latitude of a point on the unit sphere, both in `[0, 1]`, with
+/// `v` running from the south to the north pole.
+pub fn sphere_uv(p: Point3) -> (f32, f32) {
+    let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z()).atan2(p.x()) + std::f32::consts::PI;
+    (phi / (2.0 * std::f32::consts::PI), theta / std::f32::consts::PI)
+}
+
+
+/// `dp/du` and `dp/dv` for the parameterisation of `sphere_uv`, taking the
+/// hit point relative to the centre.
+fn sphere_derivatives(p: Vec3) -> (Vec3, Vec3) {
+    use std::f32::consts::PI;
+    let rho = (p.x() * p.x() + p.z() * p.z()).sqrt().max(1e-6);
+    let dpdu = 2.0 * PI * Vec3(p.z(), 0.0, -p.x());
+    let dpdv = PI * Vec3(-p.y() * p.x() / rho, rho, -p.y() * p.z() / rho);
+    (dpdu, dpdv)
+}
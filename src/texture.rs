@@ -0,0 +1,313 @@
+use std::{fs, io, path::Path, sync::Arc};
+
+use crate::vec3::{Color, Point3, Vec3};
+
+/// A colour, or a scalar in the first channel, that varies over a surface.
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Constant(Color),
+    Image(Arc<ImageTexture>),
+}
+
+impl Texture {
+    pub fn value(&self, u: f32, v: f32, _p: Point3) -> Color {
+        match self {
+            Texture::Constant(color) => *color,
+            Texture::Image(image) => image.value(u, v),
+        }
+    }
+
+    /// Textures driving scalar parameters are read from their first channel.
+    pub fn scalar(&self, u: f32, v: f32, p: Point3) -> f32 {
+        self.value(u, v, p).x()
+    }
+}
+
+impl From<Color> for Texture {
+    fn from(color: Color) -> Self {
+        Texture::Constant(color)
+    }
+}
+
+impl From<f32> for Texture {
+    fn from(v: f32) -> Self {
+        Texture::Constant(Vec3::from(v))
+    }
+}
+
+/// An image with texel values in `[0, 1]`, looked up with nearest filtering.
+/// It always has at least one texel.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, texels: Vec<Color>) -> Self {
+        assert!(width > 0 && height > 0, "image has no texels: {}x{}", width, height);
+        assert_eq!(texels.len(), width * height);
+        ImageTexture { width, height, texels }
+    }
+
+    /// Loads a binary (`P6`) or plain (`P3`) PPM, the format this tracer writes.
+    pub fn load_ppm(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        parse_ppm(&bytes).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed PPM"))
+    }
+
+    /// Loads a Radiance `.hdr` (RGBE) image, flat or run-length encoded.
+    /// Values are linear radiance and not limited to `[0, 1]`.
+    pub fn load_hdr(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        parse_hdr(&bytes).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed Radiance HDR"))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn texel(&self, x: usize, y: usize) -> Color {
+        self.texels[y * self.width + x]
+    }
+
+    /// `v` runs bottom to top, images are stored top to bottom.
+    pub fn value(&self, u: f32, v: f32) -> Color {
+        let u = u.rem_euclid(1.0);
+        let v = 1.0 - v.rem_euclid(1.0);
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.texel(x, y)
+    }
+}
+
+fn parse_ppm(bytes: &[u8]) -> Option<ImageTexture> {
+    let mut pos = 0;
+    let mut header = Vec::with_capacity(4);
+    while header.len() < 4 {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if bytes.get(pos) == Some(&b'#') {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return None;
+        }
+        header.push(std::str::from_utf8(&bytes[start..pos]).ok()?);
+    }
+
+    let width: usize = header[1].parse().ok()?;
+    let height: usize = header[2].parse().ok()?;
+    let max: f32 = header[3].parse().ok()?;
+    if width == 0 || height == 0 || !max.is_finite() || max <= 0.0 {
+        return None;
+    }
+    let count = width.checked_mul(height)?.checked_mul(3)?;
+
+    let samples: Vec<f32> = match header[0] {
+        "P6" if max < 256.0 => bytes.get(pos + 1..pos + 1 + count)?.iter().map(|&b| b as f32).collect(),
+        "P3" => std::str::from_utf8(&bytes[pos..])
+            .ok()?
+            .split_ascii_whitespace()
+            .take(count)
+            .map(|s| s.parse().ok())
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+    if samples.len() != count {
+        return None;
+    }
+
+    let texels = samples
+        .chunks_exact(3)
+        .map(|c| Vec3(c[0], c[1], c[2]) / max)
+        .collect();
+    Some(ImageTexture::new(width, height, texels))
+}
+
+/// Largest Radiance image accepted, a 16K latitude-longitude map, so a
+/// corrupt header can't ask for an absurd allocation.
+const MAX_HDR_TEXELS: usize = 16384 * 8192;
+
+fn parse_hdr(bytes: &[u8]) -> Option<ImageTexture> {
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| {
+        let start = *pos;
+        let len = bytes[start..].iter().position(|&b| b == b'\n')?;
+        *pos = start + len + 1;
+        std::str::from_utf8(&bytes[start..start + len]).ok()
+    };
+
+    if !next_line(&mut pos)?.starts_with("#?") {
+        return None;
+    }
+    loop {
+        let line = next_line(&mut pos)?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return None;
+        }
+    }
+
+    // Only the common top-to-bottom, left-to-right orientation.
+    let resolution: Vec<&str> = next_line(&mut pos)?.split_whitespace().collect();
+    if resolution.len() != 4 || resolution[0] != "-Y" || resolution[2] != "+X" {
+        return None;
+    }
+    let height: usize = resolution[1].parse().ok()?;
+    let width: usize = resolution[3].parse().ok()?;
+    let count = width.checked_mul(height)?;
+    if count == 0 || count > MAX_HDR_TEXELS {
+        return None;
+    }
+
+    let mut texels = Vec::with_capacity(count);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        let rle = (8..0x8000).contains(&width)
+            && bytes.get(pos..pos + 4)?[..2] == [2, 2]
+            && ((bytes[pos + 2] as usize) << 8 | bytes[pos + 3] as usize) == width;
+
+        if rle {
+            pos += 4;
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = *bytes.get(pos)? as usize;
+                    pos += 1;
+                    if count == 0 {
+                        return None;
+                    }
+                    if count > 128 {
+                        let run = count - 128;
+                        let value = *bytes.get(pos)?;
+                        pos += 1;
+                        for texel in scanline.get_mut(x..x + run)? {
+                            texel[channel] = value;
+                        }
+                        x += run;
+                    } else {
+                        for (texel, &value) in scanline.get_mut(x..x + count)?.iter_mut().zip(bytes.get(pos..pos + count)?) {
+                            texel[channel] = value;
+                        }
+                        pos += count;
+                        x += count;
+                    }
+                }
+            }
+        } else {
+            for texel in scanline.iter_mut() {
+                texel.copy_from_slice(bytes.get(pos..pos + 4)?);
+                pos += 4;
+            }
+        }
+
+        texels.extend(scanline.iter().map(|&[r, g, b, e]| {
+            if e == 0 {
+                return Vec3::default();
+            }
+            let f = 2f32.powi(e as i32 - 136);
+            Vec3(r as f32, g as f32, b as f32) * Vec3::from(f)
+        }));
+    }
+
+    Some(ImageTexture::new(width, height, texels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Color, b: Color) {
+        assert!((a - b).length() < 1e-6, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn ppm_binary_and_plain() {
+        let mut binary = b"P6\n# two texels\n2 1\n255\n".to_vec();
+        binary.extend_from_slice(&[255, 0, 0, 0, 51, 255]);
+        let image = parse_ppm(&binary).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_close(image.texel(0, 0), Vec3(1.0, 0.0, 0.0));
+        assert_close(image.texel(1, 0), Vec3(0.0, 0.2, 1.0));
+
+        let plain = parse_ppm(b"P3 1 2 10\n10 0 0\n0 0 5\n").unwrap();
+        assert_close(plain.texel(0, 1), Vec3(0.0, 0.0, 0.5));
+        // Rows are stored top to bottom, v runs bottom to top.
+        assert_close(plain.value(0.5, 0.9), Vec3(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ppm_rejects_malformed_input() {
+        for bytes in [
+            &b""[..],
+            b"P6",
+            b"P5 1 1 255\n\x00",
+            b"P3 0 1 255\n",
+            b"P3 1 0 255\n",
+            b"P3 1 1 0\n0 0 0",
+            b"P3 1 1 255\n1 2",
+            b"P3 1 1 255\n1 2 x",
+            b"P6 2 1 255\n\x00\x00\x00",
+            b"P6 65536 65536 255\n",
+            b"P3 18446744073709551615 18446744073709551615 255\n",
+            b"P3 -1 1 255\n0 0 0",
+        ] {
+            assert!(parse_ppm(bytes).is_none(), "{:?}", String::from_utf8_lossy(bytes));
+        }
+    }
+
+    fn hdr(resolution: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn hdr_flat_and_run_length_encoded() {
+        let flat = parse_hdr(&hdr("-Y 2 +X 1", &[128, 64, 0, 129, 0, 0, 0, 0])).unwrap();
+        assert_eq!((flat.width(), flat.height()), (1, 2));
+        assert_close(flat.texel(0, 0), Vec3(1.0, 0.5, 0.0));
+        assert_close(flat.texel(0, 1), Vec3(0.0, 0.0, 0.0));
+
+        // One run per channel, then a literal run for the exponent.
+        let mut scanline = vec![2, 2, 0, 8, 136, 128, 136, 32, 128 + 8, 0, 8];
+        scanline.extend_from_slice(&[130, 130, 130, 130, 131, 131, 131, 131]);
+        let rle = parse_hdr(&hdr("-Y 1 +X 8", &scanline)).unwrap();
+        assert_close(rle.texel(0, 0), Vec3(2.0, 0.5, 0.0));
+        assert_close(rle.texel(7, 0), Vec3(4.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn hdr_rejects_malformed_input() {
+        let pixel = [128, 128, 128, 128];
+        for bytes in [
+            hdr("-Y 1 +X 1", &pixel)[1..].to_vec(),
+            hdr("-Y 0 +X 0", &[]),
+            hdr("-Y 1 +X 0", &pixel),
+            hdr("+Y 1 +X 1", &pixel),
+            hdr("-Y 1 +X 2", &pixel),
+            hdr("-Y 100000 +X 100000", &pixel),
+            hdr("-Y 18446744073709551615 +X 2", &pixel),
+            b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\x80\x80\x80\x80".to_vec(),
+            hdr("-Y 1 +X 8", &[2, 2, 0, 8, 0]),
+        ] {
+            assert!(parse_hdr(&bytes).is_none(), "{:?}", String::from_utf8_lossy(&bytes));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-const PI: f32 = 3.1415926535897932385;
+use std::f32::consts::PI;
 
 pub fn clamp(x: f32, min: f32, max:f32) -> f32 {
     match x {
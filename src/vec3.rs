@@ -73,7 +73,7 @@ impl Vec3 {
     }
 
     pub fn random_unit_vector(rng: &mut impl Rng) -> Self {
-        return Vec3::random_in_unit_sphere(rng).unit_vector();
+        Vec3::random_in_unit_sphere(rng).unit_vector()
     }
     
     pub fn random_in_unit_disc(rng: &mut impl Rng) -> Self {
@@ -93,7 +93,7 @@ impl Vec3 {
 
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
-        return (self.0.abs() < s) && (self.1.abs() < s) && (self.2.abs() < s)
+        (self.0.abs() < s) && (self.1.abs() < s) && (self.2.abs() < s)
     }
 }
 